@@ -0,0 +1,191 @@
+use crate::config::RunChange;
+use crate::crop::CropChange;
+use crate::error::*;
+use crate::grab::GrabChange;
+use crate::walk::identity;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::num::NonZero;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+///Settings for how the batch functions treat failing files
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BatchOptions {
+    ///Stop at the first file that fails instead of processing the rest
+    pub fail_fast: bool,
+    ///How many files are processed at once (0 uses every available core), which is ignored by `fail_fast` so that no file after the failing one is touched
+    pub jobs: usize,
+}
+
+impl BatchOptions {
+    fn threads(&self) -> usize {
+        match self.jobs {
+            _ if self.fail_fast => 1,
+            0 => thread::available_parallelism().map_or(1, NonZero::get),
+            jobs => jobs,
+        }
+    }
+}
+
+///The files a batch function changed, left as they were, or failed on
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    pub succeeded: Vec<PathBuf>,
+    pub skipped: Vec<PathBuf>,
+    pub failed: Vec<Error>,
+}
+
+impl BatchReport {
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+impl fmt::Display for BatchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} succeeded, {} skipped, {} failed",
+            self.succeeded.len(),
+            self.skipped.len(),
+            self.failed.len()
+        )
+    }
+}
+
+///What a batch function did to a single file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    ///The grab offset was set by `grab`
+    Grabbed(GrabChange),
+    ///A grab chunk was added by `push_grab`
+    Pushed((i32, i32)),
+    ///The grab offset was moved by `nudge`
+    Nudged(GrabChange),
+    Cropped(CropChange),
+    ///The number of grab chunks that were removed
+    Ungrabbed(usize),
+    ///The offset that was kept if there were duplicates
+    Deduped(Option<(i32, i32)>),
+    ///The settings of the config were applied by `run`
+    Ran(RunChange),
+}
+
+impl Outcome {
+    pub fn is_changed(&self) -> bool {
+        match self {
+            Outcome::Grabbed(change) | Outcome::Nudged(change) => change.is_changed(),
+            Outcome::Pushed(_) => true,
+            Outcome::Cropped(change) => change.is_changed(),
+            Outcome::Ungrabbed(count) => *count > 0,
+            Outcome::Deduped(offset) => offset.is_some(),
+            Outcome::Ran(change) => change.is_changed(),
+        }
+    }
+}
+
+///Receives what happens to each file as a batch function goes through them (does nothing by default)
+pub trait Reporter {
+    fn processed(&mut self, _path: &Path, _outcome: &Outcome) {}
+
+    ///Only called when the batch keeps going past failing files
+    fn failed(&mut self, _error: &Error) {}
+}
+
+///Reports nothing
+impl Reporter for () {}
+
+///Runs `process` over every path (spread across `options.jobs` threads) and tells `reporter` about each of them in the order of `paths`
+pub(crate) fn run<'a>(
+    paths: impl Iterator<Item = &'a Path>,
+    options: &BatchOptions,
+    reporter: &mut impl Reporter,
+    process: impl Fn(&Path) -> Result<Outcome> + Sync,
+) -> Result<BatchReport> {
+    let paths: Vec<&Path> = paths.collect();
+    run_items(&paths, |path| path, options, reporter, |path| process(path))
+}
+
+///Same as `run` but for items that each carry the path of their png (only the first item of each file is processed)
+pub(crate) fn run_items<T: Sync>(
+    items: &[T],
+    path_of: impl Fn(&T) -> &Path,
+    options: &BatchOptions,
+    reporter: &mut impl Reporter,
+    process: impl Fn(&T) -> Result<Outcome> + Sync,
+) -> Result<BatchReport> {
+    //Two threads editing the same file at once would overwrite each other's changes, and a symlink is written through to the file it points to
+    let mut seen = HashSet::new();
+    let unique: Vec<&T> = items
+        .iter()
+        .filter(|item| seen.insert(identity(path_of(item))))
+        .collect();
+    let items = &unique[..];
+
+    let mut report = BatchReport::default();
+    let mut record = |path: &Path, result: Result<Outcome>| -> Result<()> {
+        match result {
+            Ok(outcome) => {
+                reporter.processed(path, &outcome);
+                match outcome.is_changed() {
+                    true => report.succeeded.push(path.to_path_buf()),
+                    false => report.skipped.push(path.to_path_buf()),
+                }
+            }
+            Err(e) if options.fail_fast => return Err(e.at(path)),
+            Err(e) => {
+                let e = e.at(path);
+                reporter.failed(&e);
+                report.failed.push(e);
+            }
+        }
+        Ok(())
+    };
+
+    match options.threads().min(items.len()) {
+        0 | 1 => {
+            for item in items {
+                record(path_of(item), process(item))?;
+            }
+        }
+        threads => {
+            let (next, stop) = (AtomicUsize::new(0), AtomicBool::new(false));
+            let (next, stop) = (&next, &stop);
+            thread::scope(|scope| {
+                let (sender, receiver) = mpsc::channel();
+                for _ in 0..threads {
+                    let (sender, process) = (sender.clone(), &process);
+                    scope.spawn(move || {
+                        while !stop.load(Ordering::Relaxed) {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(item) = items.get(index) else { break };
+                            if sender.send((index, process(item))).is_err() {
+                                break;
+                            }
+                        }
+                    });
+                }
+                drop(sender);
+
+                //Results arrive in whatever order the threads finish them, so they are held back until every earlier path is recorded
+                let mut pending = BTreeMap::new();
+                let mut index = 0;
+                for (i, result) in receiver {
+                    pending.insert(i, result);
+                    while let Some(result) = pending.remove(&index) {
+                        if let Err(e) = record(path_of(items[index]), result) {
+                            stop.store(true, Ordering::Relaxed);
+                            return Err(e);
+                        }
+                        index += 1;
+                    }
+                }
+                Ok(())
+            })?
+        }
+    }
+    Ok(report)
+}
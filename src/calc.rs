@@ -12,12 +12,14 @@ pub fn eval(source: &str, width: i32, height: i32) -> Result<i32> {
         match token {
             Token::Int(int) => ints.push(int),
             Token::Un(un) => match ints.pop() {
-                Some(int) => ints.push(UnFn::from(un)(int)),
+                Some(int) => ints.push(UnFn::from(un)(int).ok_or(Error::Overflow)?),
                 _ => panic!(),
             },
             Token::Bin(bin) => match (ints.pop(), ints.pop()) {
                 (Some(0), Some(_)) if bin == Binary::Div => return Err(Error::DivideByZero),
-                (Some(right), Some(left)) => ints.push(BinFn::from(bin)(left, right)),
+                (Some(right), Some(left)) => {
+                    ints.push(BinFn::from(bin)(left, right).ok_or(Error::Overflow)?)
+                }
                 _ => panic!(),
             },
             Token::Width => ints.push(width),
@@ -56,7 +58,8 @@ impl<'a> Yard<'a> {
     fn shunt(&mut self) -> Result<()> {
         let (token, source) = Token::claim(self.source, self)?;
         self.edicts[self.mode as usize](self, token)?;
-        Ok(self.source = source.trim_start())
+        self.source = source.trim_start();
+        Ok(())
     }
 
     fn expel(mut self) -> Result<Vec<Token>> {
@@ -70,8 +73,8 @@ impl<'a> Yard<'a> {
             (Some(operator), _) => self.target.push(operator),
             _ => (),
         }
-        while !self.detour.is_empty() {
-            self.target.push(self.detour.pop().unwrap());
+        while let Some(operator) = self.detour.pop() {
+            self.target.push(operator);
         }
         Ok(self.target)
     }
@@ -100,13 +103,13 @@ impl<'a> Yard<'a> {
                 }
             }
         }
-        Ok(self.detour.push(new_token))
+        self.detour.push(new_token);
+        Ok(())
     }
 }
 
-type UnFn = fn(i32) -> i32;
-type BinFn = fn(i32, i32) -> i32;
-use std::ops::*;
+type UnFn = fn(i32) -> Option<i32>;
+type BinFn = fn(i32, i32) -> Option<i32>;
 
 #[derive(Clone, Debug, PartialEq)]
 enum Token {
@@ -132,11 +135,12 @@ impl Token {
         let token = match yard.mode {
             Mode::Place => match chars.next().unwrap() {
                 '0'..='9' => {
-                    let digit_count = chars.take_while(|c| c.is_digit(10)).count() + 1;
-                    return Ok((
-                        Token::Int(source[0..digit_count].parse().unwrap()),
-                        &source[digit_count..],
-                    ));
+                    let digit_count = chars.take_while(|c| c.is_ascii_digit()).count() + 1;
+                    let digits = &source[0..digit_count];
+                    let int = digits
+                        .parse()
+                        .map_err(|_| Error::IntegerTooLarge(digits.to_string()))?;
+                    return Ok((Token::Int(int), &source[digit_count..]));
                 }
                 'w' | 'W' => Token::Width,
                 'h' | 'H' => Token::Height,
@@ -150,7 +154,7 @@ impl Token {
             },
             Mode::Bind => match chars.next().unwrap() {
                 '0'..='9' => {
-                    let digit_count = chars.take_while(|c| c.is_digit(10)).count() + 1;
+                    let digit_count = chars.take_while(|c| c.is_ascii_digit()).count() + 1;
                     return Err(Error::MisplacedInteger(source[0..digit_count].to_string()));
                 }
                 'w' | 'W' => return Err(Error::MisplacedInteger("w".into())),
@@ -186,8 +190,8 @@ enum Unary {
 impl From<Unary> for UnFn {
     fn from(un: Unary) -> Self {
         match un {
-            Unary::Affirm => i32::into,
-            Unary::Negate => i32::neg,
+            Unary::Affirm => Some,
+            Unary::Negate => i32::checked_neg,
         }
     }
 }
@@ -212,10 +216,10 @@ enum Binary {
 impl From<Binary> for BinFn {
     fn from(bin: Binary) -> Self {
         match bin {
-            Binary::Add => i32::add,
-            Binary::Sub => i32::sub,
-            Binary::Mul => i32::mul,
-            Binary::Div => i32::div,
+            Binary::Add => i32::checked_add,
+            Binary::Sub => i32::checked_sub,
+            Binary::Mul => i32::checked_mul,
+            Binary::Div => i32::checked_div,
         }
     }
 }
@@ -308,4 +312,10 @@ pub enum Error {
 
     #[error("divide by zero")]
     DivideByZero,
+
+    #[error("integer too large: '{0}'")]
+    IntegerTooLarge(String),
+
+    #[error("the result is too large")]
+    Overflow,
 }
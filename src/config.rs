@@ -0,0 +1,280 @@
+use crate::batch::{self, *};
+use crate::crc::*;
+use crate::crop::*;
+use crate::error::*;
+use crate::glob::Pattern;
+use crate::grab::*;
+use crate::png::Header;
+use crate::record::relative_to;
+use crate::save::*;
+use crate::toml::{self, Table, Value};
+use std::path::{Component, Path, PathBuf};
+
+///The name of the config file that `Config::find` looks for
+pub const config_name: &str = "putpng.toml";
+
+///What a rule does to the pngs it matches (anything left as `None` is taken from a less specific rule)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Settings {
+    ///The x and y as calc expressions
+    pub offset: Option<(String, String)>,
+    pub crop: Option<bool>,
+    ///Whether cropping also strips the ancillary chunks
+    pub strip: Option<bool>,
+}
+
+impl Settings {
+    pub fn is_empty(&self) -> bool {
+        *self == Settings::default()
+    }
+
+    //Anything set in `other` replaces what is set here
+    fn merge(&mut self, other: &Settings) {
+        if other.offset.is_some() {
+            self.offset.clone_from(&other.offset);
+        }
+        self.crop = other.crop.or(self.crop);
+        self.strip = other.strip.or(self.strip);
+    }
+}
+
+///A `[[rule]]` of the config file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    ///The rule applies to the pngs that match any of these
+    pub globs: Vec<Pattern>,
+    pub settings: Settings,
+    ///The line the rule starts on
+    pub line: usize,
+}
+
+///Rules that map globs (relative to the directory of the config file) to offsets and crop settings
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub directory: PathBuf,
+    pub rules: Vec<Rule>,
+}
+
+fn boolean(key: &str, value: &Value, line: usize) -> Result<bool> {
+    match value {
+        Value::Bool(value) => Ok(*value),
+        _ => parse_error(line, format!("{key} must be true or false")),
+    }
+}
+
+fn expression(key: &str, value: &Value, line: usize) -> Result<String> {
+    match value {
+        Value::Integer(text) | Value::String(text) if !text.trim().is_empty() => Ok(text.clone()),
+        _ => parse_error(line, format!("{key} must be a number or an expression")),
+    }
+}
+
+fn globs(value: &Value, ignore_case: bool, line: usize) -> Result<Vec<Pattern>> {
+    let values = match value {
+        Value::Array(values) if !values.is_empty() => &values[..],
+        Value::Array(_) => return parse_error(line, "glob must not be empty"),
+        value => std::slice::from_ref(value),
+    };
+    values
+        .iter()
+        .map(|value| match value {
+            Value::String(glob) => Ok(Pattern::new(glob, ignore_case)),
+            _ => parse_error(line, "glob must be a string or an array of strings"),
+        })
+        .collect()
+}
+
+fn rule(table: &Table, ignore_case: bool) -> Result<Rule> {
+    let mut globs_given = None;
+    let (mut x, mut y) = (None, None);
+    let mut settings = Settings::default();
+    for (key, value, line) in &table.fields {
+        let line = *line;
+        match key.as_str() {
+            "glob" => globs_given = Some(globs(value, ignore_case, line)?),
+            "x" => x = Some(expression(key, value, line)?),
+            "y" => y = Some(expression(key, value, line)?),
+            "crop" => settings.crop = Some(boolean(key, value, line)?),
+            "strip" => settings.strip = Some(boolean(key, value, line)?),
+            _ => return parse_error(line, format!("unknown key '{key}' in a rule")),
+        }
+    }
+    settings.offset = match (x, y) {
+        (Some(x), Some(y)) => Some((x, y)),
+        (None, None) => None,
+        _ => return parse_error(table.line, "x and y must both be given or both be left out"),
+    };
+    match globs_given {
+        Some(globs) => Ok(Rule {
+            globs,
+            settings,
+            line: table.line,
+        }),
+        None => parse_error(table.line, "the rule is missing a glob"),
+    }
+}
+
+impl Config {
+    ///Parses the text of a config file whose globs are relative to `directory`
+    pub fn parse(text: &str, directory: &Path) -> Result<Self> {
+        let tables = toml::parse(text).or_else(|(reason, line)| parse_error(line, reason))?;
+        let mut ignore_case = false;
+        for (key, value, line) in &tables[0].fields {
+            match key.as_str() {
+                "ignore_case" => ignore_case = boolean(key, value, *line)?,
+                _ => return parse_error(*line, format!("unknown key '{key}'")),
+            }
+        }
+        let rules = tables[1..]
+            .iter()
+            .map(|table| match (table.name.as_str(), table.array) {
+                ("rule", true) => rule(table, ignore_case),
+                ("rule", false) => parse_error(table.line, "rules must be written as [[rule]]"),
+                (name, _) => parse_error(table.line, format!("unknown table '{name}'")),
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            directory: directory.to_path_buf(),
+            rules,
+        })
+    }
+
+    ///Reads the config file with its globs relative to the directory it is in
+    pub fn read(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).map_err(|e| Error::from(e).at(path))?;
+        let directory = path
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        Self::parse(&text, directory).map_err(|e| e.at(path))
+    }
+
+    ///Looks for `putpng.toml` in the directory and then in each of its parents
+    pub fn find(directory: &Path) -> Option<PathBuf> {
+        let absolute = std::path::absolute(directory).ok()?;
+        let mut relative = directory.to_path_buf();
+        for _ in absolute.ancestors() {
+            let path = relative.join(config_name);
+            if path.is_file() {
+                return Some(path);
+            }
+            relative = match relative == Path::new(".") {
+                true => PathBuf::from(".."),
+                false => relative.join(".."),
+            };
+        }
+        None
+    }
+
+    ///The settings of every rule that matches the path, where each setting comes from the most specific rule that has it (or the last of them if they are just as specific), and none for a path outside the directory of the config
+    pub fn settings(&self, path: &Path) -> Settings {
+        let relative = relative_to(path, &self.directory).unwrap_or(path.to_path_buf());
+        if relative.components().next() == Some(Component::ParentDir) {
+            return Settings::default();
+        }
+        let mut matching: Vec<_> = self
+            .rules
+            .iter()
+            .filter_map(|rule| {
+                let specificity = rule
+                    .globs
+                    .iter()
+                    .filter_map(|glob| glob.specificity(&relative))
+                    .max()?;
+                Some((specificity, rule))
+            })
+            .collect();
+        matching.sort_by_key(|(specificity, _)| *specificity);
+        let mut settings = Settings::default();
+        for (_, rule) in matching {
+            settings.merge(&rule.settings);
+        }
+        settings
+    }
+}
+
+///What the settings did to a png: it is cropped first and then the offset is set
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RunChange {
+    pub grab: Option<GrabChange>,
+    pub crop: Option<CropChange>,
+}
+
+impl RunChange {
+    pub fn is_changed(&self) -> bool {
+        self.grab.is_some_and(|change| change.is_changed())
+            || self.crop.is_some_and(|change| change.is_changed())
+    }
+
+    ///The offset the png ends up with
+    pub fn offset(&self) -> Option<(i32, i32)> {
+        match (self.grab, self.crop) {
+            (Some(grab), _) => Some(grab.after),
+            (None, Some(crop)) if crop.is_changed() => Some(crop.grab.after),
+            (None, Some(crop)) => crop.grab.before,
+            (None, None) => None,
+        }
+    }
+}
+
+///Same as `run` but for a png held in memory
+pub fn run_bytes(bytes: &mut Vec<u8>, crc: &Crc32, settings: &Settings) -> Result<RunChange> {
+    //Cropping first means the offset is always worked out from the same image, so running the settings again changes nothing
+    let crop = match settings.crop {
+        Some(true) => Some(crop_bytes(bytes, crc, settings.strip == Some(true))?),
+        _ => None,
+    };
+    let grab = match &settings.offset {
+        Some((source_x, source_y)) => {
+            let header = Header::read(&mut bytes.as_slice(), crc)?;
+            let dimensions = (header.width as i32, header.height as i32);
+            let (x, y) = both(
+                evaluate(source_x, None, dimensions),
+                evaluate(source_y, None, dimensions),
+            )?;
+            Some(set_grab_bytes(bytes, crc, x, y)?)
+        }
+        None => None,
+    };
+    Ok(RunChange { grab, crop })
+}
+
+///Crops the specified png and then sets its offset (with `w` and `h` being the cropped size) according to the settings (the file is left untouched if neither changes anything)
+pub fn run(
+    path: &Path,
+    crc: &Crc32,
+    settings: &Settings,
+    options: &SaveOptions,
+) -> Result<RunChange> {
+    edit_with(
+        path,
+        options,
+        |bytes| run_bytes(bytes, crc, settings),
+        |change| match change.crop {
+            Some(crop) if crop.is_changed() => None,
+            _ => change.grab.and_then(|grab| grab.before),
+        },
+    )
+}
+
+///Runs the settings the config has for each of the specified pngs, leaving out the ones that no rule matches
+pub fn run_all<'a>(
+    paths: impl Iterator<Item = &'a Path>,
+    crc: &Crc32,
+    config: &Config,
+    batch: &BatchOptions,
+    options: &SaveOptions,
+    reporter: &mut impl Reporter,
+) -> Result<BatchReport> {
+    let items: Vec<(&Path, Settings)> = paths
+        .map(|path| (path, config.settings(path)))
+        .filter(|(_, settings)| !settings.is_empty())
+        .collect();
+    batch::run_items(
+        &items,
+        |(path, _)| path,
+        batch,
+        reporter,
+        |(path, settings)| run(path, crc, settings, options).map(Outcome::Ran),
+    )
+}
@@ -1,32 +1,73 @@
+use crate::batch::{self, *};
 use crate::crc::*;
+use crate::error::*;
 use crate::grab::*;
-use image::*;
+use crate::png::{Chunk, PngFile};
+use crate::save::*;
+use ::png::{BitDepth, ColorType, Decoder, Encoder, Transformations};
 use std::path::Path;
 
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
-
-struct ImageCropper<'a> {
-    image: DynamicImage,
+//Works on the undecoded scanlines so that the color type, bit depth, and palette are kept as they are
+struct ImageCropper {
+    data: Vec<u8>,
+    line_size: usize,
     width: u32,
     height: u32,
-    path: &'a Path,
+    color_type: ColorType,
+    bit_depth: BitDepth,
+    palette: Option<Vec<u8>>,
+    trns: Option<Vec<u8>>,
 }
 
-impl<'a> ImageCropper<'a> {
-    fn open(path: &'a Path) -> Result<Self> {
-        let image = image::open(path)?;
-        let (width, height) = image.dimensions();
+impl ImageCropper {
+    fn new(bytes: &[u8], png: &PngFile) -> Result<Self> {
+        let mut decoder = Decoder::new(bytes);
+        decoder.set_transformations(Transformations::IDENTITY);
+        let mut reader = decoder.read_info()?;
+        let mut data = vec![0; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut data)?;
+        let info = reader.info();
         Ok(Self {
-            image,
-            width,
-            height,
-            path,
+            data,
+            line_size: frame.line_size,
+            width: frame.width,
+            height: frame.height,
+            color_type: frame.color_type,
+            bit_depth: frame.bit_depth,
+            palette: info.palette.as_ref().map(|p| p.to_vec()),
+            trns: png.find(b"tRNS").map(|c| c.data.clone()),
         })
     }
 
+    fn sample(&self, y: u32, index: usize) -> u16 {
+        let row = &self.data[y as usize * self.line_size..];
+        match self.bit_depth as usize {
+            16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+            8 => row[index] as u16,
+            depth => {
+                let bit = index * depth;
+                (row[bit / 8] >> (8 - depth - bit % 8)) as u16 & ((1 << depth) - 1)
+            }
+        }
+    }
+
     fn is_visible(&self, x: u32, y: u32) -> bool {
-        let pixel = self.image.get_pixel(x, y).to_rgba();
-        pixel[3] != 0
+        let channels = self.color_type.samples();
+        let first = x as usize * channels;
+        match (self.color_type, &self.trns) {
+            (ColorType::GrayscaleAlpha | ColorType::Rgba, _) => {
+                self.sample(y, first + channels - 1) != 0
+            }
+            (ColorType::Indexed, Some(trns)) => trns
+                .get(self.sample(y, first) as usize)
+                .is_none_or(|alpha| *alpha != 0),
+            (ColorType::Grayscale | ColorType::Rgb, Some(trns)) if trns.len() >= channels * 2 => {
+                (0..channels).any(|c| {
+                    self.sample(y, first + c) != u16::from_be_bytes([trns[c * 2], trns[c * 2 + 1]])
+                })
+            }
+            _ => true,
+        }
     }
 
     fn visible_left(&self) -> u32 {
@@ -55,37 +96,130 @@ impl<'a> ImageCropper<'a> {
             .unwrap_or(self.height - 1)
     }
 
-    fn save(&self) -> Result<(i32, i32)> {
+    ///The left, top, width, and height of the visible part of the image
+    fn bounds(&self) -> (u32, u32, u32, u32) {
         let (left, right, top, bottom) = (
             self.visible_left(),
             self.visible_right(),
             self.visible_top(),
             self.visible_bottom(),
         );
-        let image = imageops::crop_imm(&self.image, left, top, right - left + 1, bottom - top + 1)
-            .to_image();
-        std::fs::remove_file(&self.path)?;
-        image.save(&self.path)?;
-        Ok((left as i32, top as i32))
+        (left, top, right - left + 1, bottom - top + 1)
+    }
+
+    fn crop(&self, (left, top, width, height): (u32, u32, u32, u32)) -> Vec<u8> {
+        let bits = self.color_type.samples() * self.bit_depth as usize;
+        let (left, width) = (left as usize, width as usize);
+        let mut data = vec![];
+        for y in top..top + height {
+            if bits.is_multiple_of(8) {
+                let row = &self.data[y as usize * self.line_size..];
+                data.extend_from_slice(&row[left * bits / 8..(left + width) * bits / 8]);
+            } else {
+                let mut row = vec![0u8; (width * bits).div_ceil(8)];
+                for x in 0..width {
+                    let bit = x * bits;
+                    row[bit / 8] |= (self.sample(y, left + x) as u8) << (8 - bits - bit % 8);
+                }
+                data.extend(row);
+            }
+        }
+        data
+    }
+
+    ///Encodes the cropped scanlines and returns the resulting IDAT chunks
+    fn encode(&self, crc: &Crc32, bounds: (u32, u32, u32, u32)) -> Result<Vec<Chunk>> {
+        let mut bytes = vec![];
+        let mut encoder = Encoder::new(&mut bytes, bounds.2, bounds.3);
+        encoder.set_color(self.color_type);
+        encoder.set_depth(self.bit_depth);
+        if let Some(palette) = &self.palette {
+            encoder.set_palette(palette.as_slice());
+        }
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.crop(bounds))?;
+        writer.finish()?;
+        let png = PngFile::read(&mut bytes.as_slice(), crc)?;
+        Ok(png.chunks.into_iter().filter(|c| c.is(b"IDAT")).collect())
     }
 }
 
-///Crops the specified png while preserving the relative offset
-pub fn crop(path: &Path, crc: &Crc32) -> Result<()> {
-    let new_offset = {
-        let grab_offset = read_grab(path)?.unwrap_or_default();
-        let crop_offset = ImageCropper::open(path)?.save()?;
-        (grab_offset.0 - crop_offset.0, grab_offset.1 - crop_offset.1)
+fn resize_header(png: &mut PngFile, crc: &Crc32, width: u32, height: u32) {
+    let mut data = png.chunks[0].data.clone();
+    data[0..4].copy_from_slice(&width.to_be_bytes());
+    data[4..8].copy_from_slice(&height.to_be_bytes());
+    //The scanlines are always written back without interlacing
+    data[12] = 0;
+    png.chunks[0] = Chunk::new(crc, b"IHDR", data);
+}
+
+///The size of a png before it was cropped and the visible area it was cropped to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CropChange {
+    pub size: (u32, u32),
+    ///The left, top, width, and height of the visible area
+    pub bounds: (u32, u32, u32, u32),
+    pub grab: GrabChange,
+    ///Whether any ancillary chunks were stripped
+    pub stripped: bool,
+}
+
+impl CropChange {
+    pub fn is_changed(&self) -> bool {
+        self.bounds != (0, 0, self.size.0, self.size.1) || self.stripped
+    }
+}
+
+///Crops the specified png while preserving the relative offset, its color type and palette, and unless `strip` is set, its ancillary chunks (the file is left untouched if there is nothing to crop or strip)
+pub fn crop(path: &Path, crc: &Crc32, strip: bool, options: &SaveOptions) -> Result<CropChange> {
+    edit(path, options, |bytes| crop_bytes(bytes, crc, strip))
+}
+
+///Same as `crop` but for a png held in memory (the bytes are left untouched if there is nothing to crop or strip)
+pub fn crop_bytes(bytes: &mut Vec<u8>, crc: &Crc32, strip: bool) -> Result<CropChange> {
+    let mut png = PngFile::read(&mut bytes.as_slice(), crc)?;
+    let cropper = ImageCropper::new(bytes, &png)?;
+    let bounds = cropper.bounds();
+    let grab_offset = first_grab(&png)?;
+    let after = moved(
+        grab_offset.unwrap_or_default(),
+        (-(bounds.0 as i64), -(bounds.1 as i64)),
+    )?;
+    let strippable = |c: &Chunk| strip && c.is_ancillary() && !c.is(b"grAb") && !c.is(b"tRNS");
+    let change = CropChange {
+        size: (cropper.width, cropper.height),
+        bounds,
+        grab: GrabChange {
+            before: grab_offset,
+            after,
+        },
+        stripped: png.chunks.iter().any(strippable),
     };
-    push_grab(&path, crc, new_offset.0, new_offset.1)?;
-    Ok(())
+    if !change.is_changed() {
+        return Ok(change);
+    }
+
+    let data = cropper.encode(crc, bounds)?;
+    resize_header(&mut png, crc, bounds.2, bounds.3);
+    png.chunks.retain(|c| !c.is(b"grAb") && !strippable(c));
+    let index = png.position(b"IDAT").unwrap_or(png.chunks.len() - 1);
+    png.chunks.retain(|c| !c.is(b"IDAT"));
+    png.chunks.splice(index..index, data);
+    insert_grab(&mut png, crc, change.grab.after.0, change.grab.after.1);
+    *bytes = png.to_bytes();
+    Ok(change)
 }
 
 ///Crops all the specified pngs while preserving relative grab offsets
-pub fn crop_all<'a>(paths: impl Iterator<Item = &'a Path>, crc: &Crc32) -> Result<()> {
-    for path in paths {
-        crop(path, crc)?;
-        println!("Cropped {path:?} successfully!");
-    }
-    Ok(())
+pub fn crop_all<'a>(
+    paths: impl Iterator<Item = &'a Path>,
+    crc: &Crc32,
+    strip: bool,
+    batch: &BatchOptions,
+    options: &SaveOptions,
+    reporter: &mut impl Reporter,
+) -> Result<BatchReport> {
+    batch::run(paths, batch, reporter, |path| {
+        crop(path, crc, strip, options).map(Outcome::Cropped)
+    })
 }
@@ -0,0 +1,69 @@
+//The position of the json and toml readers in their text, which are written by hand rather than with serde so that every manifest row and config key keeps its line for error messages
+
+///What went wrong and the line it happened on
+pub(crate) type Error = (String, usize);
+
+///How many arrays and objects can be nested inside each other
+const max_depth: usize = 128;
+
+pub(crate) struct Cursor<'a> {
+    pub(crate) text: &'a [u8],
+    pub(crate) index: usize,
+    ///The line that `counted` is on
+    line: usize,
+    ///How far the lines have been counted (the index only ever moves forward)
+    counted: usize,
+    depth: usize,
+}
+
+impl<'a> Cursor<'a> {
+    pub(crate) fn new(text: &'a str) -> Self {
+        Self {
+            text: text.as_bytes(),
+            index: 0,
+            line: 1,
+            counted: 0,
+            depth: 0,
+        }
+    }
+
+    pub(crate) fn get(&self) -> Option<u8> {
+        self.text.get(self.index).copied()
+    }
+
+    ///The line the index is on
+    pub(crate) fn line(&mut self) -> usize {
+        let text = self.text;
+        self.line += text[self.counted..self.index]
+            .iter()
+            .filter(|b| **b == b'\n')
+            .count();
+        self.counted = self.index;
+        self.line
+    }
+
+    pub(crate) fn error<T>(&mut self, message: &str) -> Result<T, Error> {
+        Err((message.into(), self.line()))
+    }
+
+    ///Goes one array or object deeper, failing past `max_depth`
+    pub(crate) fn enter(&mut self) -> Result<(), Error> {
+        self.depth += 1;
+        match self.depth > max_depth {
+            true => self.error("nested too deeply"),
+            false => Ok(()),
+        }
+    }
+
+    pub(crate) fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    ///The four hex digits starting at `start`
+    pub(crate) fn hex(&self, start: usize) -> Option<u32> {
+        self.text
+            .get(start..start + 4)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+    }
+}
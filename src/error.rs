@@ -0,0 +1,154 @@
+use crate::{calc, png};
+use std::path::{Path, PathBuf};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+fn at(path: &Option<PathBuf>) -> String {
+    path.as_ref()
+        .map(|p| format!("{p:?}: "))
+        .unwrap_or_default()
+}
+
+fn for_path(path: &Option<PathBuf>) -> String {
+    path.as_ref()
+        .map(|p| format!(" for {p:?}"))
+        .unwrap_or_default()
+}
+
+fn lines(errors: &[Error]) -> String {
+    errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{}{source}", at(path))]
+    Io {
+        path: Option<PathBuf>,
+        source: std::io::Error,
+    },
+
+    #[error("{}{source}", at(path))]
+    Png {
+        path: Option<PathBuf>,
+        source: png::Error,
+    },
+
+    #[error("{}malformed {kind} chunk", at(path))]
+    Chunk { path: Option<PathBuf>, kind: String },
+
+    #[error("{}{source}", at(path))]
+    Decode {
+        path: Option<PathBuf>,
+        source: ::png::DecodingError,
+    },
+
+    #[error("{}{source}", at(path))]
+    Encode {
+        path: Option<PathBuf>,
+        source: ::png::EncodingError,
+    },
+
+    #[error("error in '{expression}'{}: {source}", for_path(path))]
+    Expression {
+        expression: String,
+        path: Option<PathBuf>,
+        source: calc::Error,
+    },
+
+    #[error("{}", lines(.0))]
+    Expressions(Vec<Error>),
+
+    #[error("{}only {count} grAb chunks", at(path))]
+    MissingGrab { path: Option<PathBuf>, count: usize },
+
+    #[error("{}the offset ({}, {}) moved by ({}, {}) is out of range", at(path), offset.0, offset.1, delta.0, delta.1)]
+    Overflow {
+        path: Option<PathBuf>,
+        offset: (i32, i32),
+        delta: (i64, i64),
+    },
+
+    #[error("{}malformed journal entry on line {line}", at(path))]
+    Manifest { path: Option<PathBuf>, line: usize },
+
+    #[error("{}line {line}: {reason}", at(path))]
+    Parse {
+        path: Option<PathBuf>,
+        line: usize,
+        reason: String,
+    },
+}
+
+///A `Parse` error for the line of a manifest or config file (the path is attached by whoever read the file)
+pub(crate) fn parse_error<T>(line: usize, reason: impl Into<String>) -> Result<T> {
+    Err(Error::Parse {
+        path: None,
+        line,
+        reason: reason.into(),
+    })
+}
+
+impl Error {
+    ///The path of the file that caused the error if there is one
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Error::Io { path, .. }
+            | Error::Png { path, .. }
+            | Error::Chunk { path, .. }
+            | Error::Decode { path, .. }
+            | Error::Encode { path, .. }
+            | Error::Expression { path, .. }
+            | Error::MissingGrab { path, .. }
+            | Error::Overflow { path, .. }
+            | Error::Manifest { path, .. }
+            | Error::Parse { path, .. } => path.as_deref(),
+            Error::Expressions(errors) => errors.iter().find_map(Error::path),
+        }
+    }
+
+    ///Attaches the path to the error unless it already has one
+    pub(crate) fn at(mut self, file: &Path) -> Self {
+        match &mut self {
+            Error::Io { path, .. }
+            | Error::Png { path, .. }
+            | Error::Chunk { path, .. }
+            | Error::Decode { path, .. }
+            | Error::Encode { path, .. }
+            | Error::Expression { path, .. }
+            | Error::MissingGrab { path, .. }
+            | Error::Overflow { path, .. }
+            | Error::Manifest { path, .. }
+            | Error::Parse { path, .. } => {
+                path.get_or_insert_with(|| file.to_path_buf());
+            }
+            Error::Expressions(errors) => {
+                *errors = std::mem::take(errors)
+                    .into_iter()
+                    .map(|e| e.at(file))
+                    .collect();
+            }
+        }
+        self
+    }
+}
+
+macro_rules! from_source {
+    ($($variant:ident: $source:ty),*) => {
+        $(impl From<$source> for Error {
+            fn from(source: $source) -> Self {
+                Error::$variant { path: None, source }
+            }
+        })*
+    };
+}
+
+from_source!(
+    Io: std::io::Error,
+    Png: png::Error,
+    Decode: ::png::DecodingError,
+    Encode: ::png::EncodingError
+);
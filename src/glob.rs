@@ -0,0 +1,172 @@
+use std::path::{Component, Path};
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    ///`**`, which matches any number of components
+    AnyDepth,
+    Glob(Vec<char>),
+}
+
+///A glob pattern matched against the components of a path, where `*` and `?` never cross a `/` but `**` matches any number of components
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pattern {
+    segments: Vec<Segment>,
+    ///Whether the pattern must match from the first component (it started with `/`)
+    anchored: bool,
+    ignore_case: bool,
+}
+
+impl Pattern {
+    ///Without a `/`, the pattern matches any single component, otherwise it matches any run of components (or only the first ones if it starts with `/`)
+    pub fn new(pattern: &str, ignore_case: bool) -> Self {
+        let pattern = match ignore_case {
+            true => pattern.to_lowercase(),
+            false => pattern.to_string(),
+        };
+        let anchored = pattern.starts_with('/');
+        let segments = pattern
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(|s| match s {
+                "**" => Segment::AnyDepth,
+                s => Segment::Glob(s.chars().collect()),
+            })
+            .collect();
+        Self {
+            segments,
+            anchored,
+            ignore_case,
+        }
+    }
+
+    fn components(&self, path: &Path) -> Vec<String> {
+        path.components()
+            .filter_map(|c| match c {
+                Component::Normal(name) => Some(name.to_string_lossy()),
+                _ => None,
+            })
+            .map(|name| match self.ignore_case {
+                true => name.to_lowercase(),
+                false => name.into_owned(),
+            })
+            .collect()
+    }
+
+    ///How narrowly the pattern picks out the path (or None if it does not match): how many components deep the last component it names without wildcards is, then how many characters of the pattern are not wildcards
+    pub fn specificity(&self, path: &Path) -> Option<(usize, usize)> {
+        let depth = self.depth(path, false)?;
+        let literals = self
+            .segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Glob(glob) => literal_count(glob),
+                Segment::AnyDepth => 0,
+            })
+            .sum();
+        Some((depth, literals))
+    }
+
+    fn depth(&self, path: &Path, to_end: bool) -> Option<usize> {
+        let components = self.components(path);
+        match self.anchored {
+            true => match_depth(&self.segments, &components, 0, to_end),
+            false => (0..components.len())
+                .filter_map(|i| match_depth(&self.segments, &components[i..], i, to_end))
+                .max(),
+        }
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        self.depth(path, false).is_some()
+    }
+
+    ///Same as `matches` but the pattern has to match up to the last component of the path (so `a` matches `b/a` but not `a/b`)
+    pub fn matches_end(&self, path: &Path) -> bool {
+        self.depth(path, true).is_some()
+    }
+}
+
+//Returns how deep the deepest component matched by a glob without wildcards is (out of every way the segments can match), or None if they cannot match (or leave components over when `to_end` is set)
+fn match_depth(
+    segments: &[Segment],
+    components: &[String],
+    offset: usize,
+    to_end: bool,
+) -> Option<usize> {
+    match segments.split_first() {
+        None if to_end && !components.is_empty() => None,
+        None => Some(0),
+        Some((Segment::AnyDepth, rest)) => (0..=components.len())
+            .filter_map(|i| match_depth(rest, &components[i..], offset + i, to_end))
+            .max(),
+        Some((Segment::Glob(glob), rest)) => {
+            let (first, others) = components.split_first()?;
+            let name: Vec<char> = first.chars().collect();
+            if !matches_glob(glob, &name) {
+                return None;
+            }
+            let depth = match_depth(rest, others, offset + 1, to_end)?;
+            match literal_count(glob) == glob.len() {
+                true => Some(depth.max(offset + 1)),
+                false => Some(depth),
+            }
+        }
+    }
+}
+
+//Returns the length of the class and whether it matched, or None if it is not closed (and so is a literal `[`)
+fn match_class(glob: &[char], c: char) -> Option<(usize, bool)> {
+    let mut i = 1;
+    let negated = matches!(glob.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        match glob.get(i)? {
+            ']' if !first => return Some((i + 1, matched != negated)),
+            &start
+                if glob.get(i + 1) == Some(&'-') && glob.get(i + 2).is_some_and(|e| *e != ']') =>
+            {
+                matched |= (start..=glob[i + 2]).contains(&c);
+                i += 3;
+            }
+            &single => {
+                matched |= single == c;
+                i += 1;
+            }
+        }
+        first = false;
+    }
+}
+
+//A class counts as a single literal since it stands for one character
+fn literal_count(glob: &[char]) -> usize {
+    match glob.split_first() {
+        None => 0,
+        Some(('*' | '?', rest)) => literal_count(rest),
+        Some(('\\', rest)) if !rest.is_empty() => 1 + literal_count(&rest[1..]),
+        Some(('[', _)) => match match_class(glob, '\0') {
+            Some((length, _)) => 1 + literal_count(&glob[length..]),
+            None => 1 + literal_count(&glob[1..]),
+        },
+        Some((_, rest)) => 1 + literal_count(rest),
+    }
+}
+
+fn matches_glob(glob: &[char], name: &[char]) -> bool {
+    match glob.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|i| matches_glob(rest, &name[i..])),
+        Some(('?', rest)) => !name.is_empty() && matches_glob(rest, &name[1..]),
+        Some(('\\', rest)) if !rest.is_empty() => {
+            name.first() == rest.first() && matches_glob(&rest[1..], &name[1..])
+        }
+        Some(('[', _)) if !name.is_empty() => match match_class(glob, name[0]) {
+            Some((length, matched)) => matched && matches_glob(&glob[length..], &name[1..]),
+            None => name[0] == '[' && matches_glob(&glob[1..], &name[1..]),
+        },
+        Some((c, rest)) => name.first() == Some(c) && matches_glob(rest, &name[1..]),
+    }
+}
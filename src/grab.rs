@@ -1,211 +1,389 @@
+use crate::batch::{self, *};
 use crate::calc;
 use crate::crc::*;
-use image::*;
-use std::fs::*;
-use std::io::*;
+use crate::error::*;
+use crate::png::{Chunk, Header, PngFile};
+use crate::record::{Row, check_duplicates};
+use crate::save::*;
+use crate::walk::identity;
+use std::io::Read;
 use std::path::Path;
 
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+fn create_grab(crc: &Crc32, x: i32, y: i32) -> Chunk {
+    Chunk::new(crc, b"grAb", [x.to_be_bytes(), y.to_be_bytes()].concat())
+}
 
-const default_grab_seek: std::io::SeekFrom = SeekFrom::Start(33);
+fn parse_grab(chunk: &Chunk) -> Result<(i32, i32)> {
+    match chunk.data[..] {
+        [x0, x1, x2, x3, y0, y1, y2, y3] => Ok((
+            i32::from_be_bytes([x0, x1, x2, x3]),
+            i32::from_be_bytes([y0, y1, y2, y3]),
+        )),
+        _ => Err(Error::Chunk {
+            path: None,
+            kind: "grAb".into(),
+        }),
+    }
+}
 
-fn read_header(file: &mut File) -> Result<(u32, [u8; 4])> {
-    let mut buffer = <[u8; 4]>::default();
-    file.read(&mut buffer)?;
-    let length = u32::from_be_bytes(buffer);
-    file.read(&mut buffer)?;
-    Ok((length, buffer))
+pub(crate) fn first_grab(png: &PngFile) -> Result<Option<(i32, i32)>> {
+    png.find(b"grAb").map(parse_grab).transpose()
 }
 
-fn write_into(file: &mut File, seek: SeekFrom, data: &[u8]) -> Result<()> {
-    let mut buffer = Vec::new();
-    file.seek(seek)?;
-    file.read_to_end(&mut buffer)?;
-    file.seek(seek)?;
-    file.write(data)?;
-    file.write(&buffer)?;
-    Ok(())
+pub(crate) fn all_grabs(png: &PngFile) -> Result<Vec<(i32, i32)>> {
+    png.chunks
+        .iter()
+        .filter(|c| c.is(b"grAb"))
+        .map(parse_grab)
+        .collect()
 }
 
-fn create_grab(crc: &Crc32, x: i32, y: i32) -> Vec<u8> {
-    let body: &[u8] = &["grAb".as_bytes(), &x.to_be_bytes(), &y.to_be_bytes()].concat();
-    [
-        &8u32.to_be_bytes(),
-        body,
-        &crc.calculate(body).to_be_bytes(),
-    ]
-    .concat()
+///The offset moved by the delta, or an error if the result does not fit in an i32
+pub(crate) fn moved((x, y): (i32, i32), (dx, dy): (i64, i64)) -> Result<(i32, i32)> {
+    let add = |value: i32, delta: i64| i32::try_from(value as i64 + delta).ok();
+    match (add(x, dx), add(y, dy)) {
+        (Some(x), Some(y)) => Ok((x, y)),
+        _ => Err(Error::Overflow {
+            path: None,
+            offset: (x, y),
+            delta: (dx, dy),
+        }),
+    }
 }
 
-fn insert_grab(file: &mut File, crc: &Crc32, x: i32, y: i32) -> Result<()> {
-    Ok(write_into(
-        file,
-        default_grab_seek,
-        &create_grab(crc, x, y),
-    )?)
+pub(crate) fn insert_grab(png: &mut PngFile, crc: &Crc32, x: i32, y: i32) {
+    let index = png.position(b"IHDR").map_or(0, |i| i + 1);
+    png.chunks.insert(index, create_grab(crc, x, y));
 }
 
-///Tries to read the grab chunk if there is one
-pub fn read_grab(path: &Path) -> Result<Option<(i32, i32)>> {
-    let mut file = File::open(path)?;
-
-    file.seek(default_grab_seek)?;
-    let (mut length, mut name) = read_header(&mut file)?;
-
-    while name != "IDAT".as_bytes() {
-        if name == "grAb".as_bytes() {
-            let mut buffer = <[u8; 4]>::default();
-            file.read(&mut buffer)?;
-            let x = i32::from_be_bytes(buffer);
-            file.read(&mut buffer)?;
-            let y = i32::from_be_bytes(buffer);
-            return Ok(Some((x, y)));
-        }
-        file.seek(SeekFrom::Current(length as i64 + 4))?;
-        (length, name) = read_header(&mut file)?;
+fn set_grab(png: &mut PngFile, crc: &Crc32, x: i32, y: i32) -> GrabChange {
+    let before = png.find(b"grAb").and_then(|c| parse_grab(c).ok());
+    match png.position(b"grAb") {
+        Some(_) if before == Some((x, y)) => (),
+        Some(index) => png.chunks[index] = create_grab(crc, x, y),
+        None => insert_grab(png, crc, x, y),
+    }
+    GrabChange {
+        before,
+        after: (x, y),
+    }
+}
+
+///The grab offset of a png before and after it was changed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GrabChange {
+    pub before: Option<(i32, i32)>,
+    pub after: (i32, i32),
+}
+
+impl GrabChange {
+    pub fn is_changed(&self) -> bool {
+        self.before != Some(self.after)
     }
+}
+
+//Only rewrites the bytes when the png was actually changed
+fn edit_png<T>(
+    bytes: &mut Vec<u8>,
+    crc: &Crc32,
+    edit: impl FnOnce(&mut PngFile) -> Result<T>,
+) -> Result<T> {
+    let mut png = PngFile::read(&mut bytes.as_slice(), crc)?;
+    let original = png.clone();
+    let result = edit(&mut png)?;
+    if png != original {
+        *bytes = png.to_bytes();
+    }
+    Ok(result)
+}
+
+///Tries to read the grab chunk of a png from the reader if there is one
+pub fn read_grab_from(reader: &mut impl Read, crc: &Crc32) -> Result<Option<(i32, i32)>> {
+    first_grab(&PngFile::read(reader, crc)?)
+}
+
+///Tries to read the grab chunk if there is one
+pub fn read_grab(path: &Path, crc: &Crc32) -> Result<Option<(i32, i32)>> {
+    first_grab(&PngFile::open(path, crc)?).map_err(|e| e.at(path))
+}
+
+///Reads every grab chunk of a png from the reader in the order they appear
+pub fn read_grabs_from(reader: &mut impl Read, crc: &Crc32) -> Result<Vec<(i32, i32)>> {
+    all_grabs(&PngFile::read(reader, crc)?)
+}
+
+///Reads every grab chunk in the order they appear
+pub fn read_grabs(path: &Path, crc: &Crc32) -> Result<Vec<(i32, i32)>> {
+    let png = PngFile::open(path, crc)?;
+    all_grabs(&png).map_err(|e| e.at(path))
+}
 
-    Ok(None)
+///Same as `push_grab` but for a png held in memory
+pub fn push_grab_bytes(bytes: &mut Vec<u8>, crc: &Crc32, x: i32, y: i32) -> Result<()> {
+    edit_png(bytes, crc, |png| {
+        insert_grab(png, crc, x, y);
+        Ok(())
+    })
 }
 
 ///Adds a new grab chunk to the specified png (will have duplicate grab chunks if there is already a grab chunk)
-pub fn push_grab(path: &Path, crc: &Crc32, x: i32, y: i32) -> Result<()> {
-    let mut file = File::options().read(true).write(true).open(path)?;
-    insert_grab(&mut file, crc, x, y)?;
-    Ok(())
-}
-
-///Adds or overwrites a grab chunck to the specified png
-pub fn grab(path: &Path, crc: &Crc32, x: i32, y: i32) -> Result<()> {
-    let mut file = File::options().read(true).write(true).open(path)?;
-
-    file.seek(default_grab_seek)?;
-    let (mut length, mut name) = read_header(&mut file)?;
-
-    while name != "IDAT".as_bytes() {
-        if name == "grAb".as_bytes() {
-            let offset: &[u8] = &[x.to_be_bytes(), y.to_be_bytes()].concat();
-            file.write(
-                &[
-                    offset,
-                    &crc.calculate(&[&name, offset].concat()).to_be_bytes(),
-                ]
-                .concat(),
-            )?;
-            return Ok(());
+pub fn push_grab(path: &Path, crc: &Crc32, x: i32, y: i32, options: &SaveOptions) -> Result<()> {
+    edit(path, options, |bytes| push_grab_bytes(bytes, crc, x, y))
+}
+
+///Same as `grab` but for a png held in memory (the bytes are left untouched if it already has the offset)
+pub fn set_grab_bytes(bytes: &mut Vec<u8>, crc: &Crc32, x: i32, y: i32) -> Result<GrabChange> {
+    edit_png(bytes, crc, |png| Ok(set_grab(png, crc, x, y)))
+}
+
+///Adds or overwrites a grab chunck to the specified png (the file is left untouched if it already has the offset)
+pub fn grab(path: &Path, crc: &Crc32, x: i32, y: i32, options: &SaveOptions) -> Result<GrabChange> {
+    edit_with(
+        path,
+        options,
+        |bytes| set_grab_bytes(bytes, crc, x, y),
+        |change| change.before,
+    )
+}
+
+///Same as `nudge` but for a png held in memory
+pub fn nudge_bytes(bytes: &mut Vec<u8>, crc: &Crc32, dx: i32, dy: i32) -> Result<GrabChange> {
+    edit_png(bytes, crc, |png| {
+        let offset = first_grab(png)?.unwrap_or_default();
+        let (x, y) = moved(offset, (dx as i64, dy as i64))?;
+        Ok(set_grab(png, crc, x, y))
+    })
+}
+
+///Adds the given deltas to the current grab offset (or to (0, 0) if there is none)
+pub fn nudge(
+    path: &Path,
+    crc: &Crc32,
+    dx: i32,
+    dy: i32,
+    options: &SaveOptions,
+) -> Result<GrabChange> {
+    edit_with(
+        path,
+        options,
+        |bytes| nudge_bytes(bytes, crc, dx, dy),
+        |change| change.before,
+    )
+}
+
+///Same as `remove_grab` but for a png held in memory
+pub fn remove_grab_bytes(bytes: &mut Vec<u8>, crc: &Crc32) -> Result<usize> {
+    edit_png(bytes, crc, |png| {
+        let count = png.chunks.len();
+        png.chunks.retain(|c| !c.is(b"grAb"));
+        Ok(count - png.chunks.len())
+    })
+}
+
+///Removes every grab chunk from the specified png and returns how many were removed
+pub fn remove_grab(path: &Path, crc: &Crc32, options: &SaveOptions) -> Result<usize> {
+    edit(path, options, |bytes| remove_grab_bytes(bytes, crc))
+}
+
+///Removes every grab chunk from all the specified pngs
+pub fn remove_grab_all<'a>(
+    paths: impl Iterator<Item = &'a Path>,
+    crc: &Crc32,
+    batch: &BatchOptions,
+    options: &SaveOptions,
+    reporter: &mut impl Reporter,
+) -> Result<BatchReport> {
+    batch::run(paths, batch, reporter, |path| {
+        remove_grab(path, crc, options).map(Outcome::Ungrabbed)
+    })
+}
+
+///Which grab chunk to keep when collapsing duplicates
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Keep {
+    First,
+    Last,
+    ///One-based position of the grab chunk
+    Nth(usize),
+}
+
+impl std::str::FromStr for Keep {
+    type Err = String;
+
+    fn from_str(source: &str) -> std::result::Result<Self, Self::Err> {
+        match source {
+            "first" => Ok(Keep::First),
+            "last" => Ok(Keep::Last),
+            _ => match source.parse() {
+                Ok(0) | Err(_) => Err(format!(
+                    "expected 'first', 'last', or a position starting at 1, found '{source}'"
+                )),
+                Ok(n) => Ok(Keep::Nth(n)),
+            },
         }
-        file.seek(SeekFrom::Current(length as i64 + 4))?;
-        (length, name) = read_header(&mut file)?;
     }
-    insert_grab(&mut file, &crc, x, y)?;
-    Ok(())
 }
 
-///Adds grab chunks to the specified pngs using either the `push_grab` or `grab` functions based on `should_push`
+///Same as `dedupe` but for a png held in memory
+pub fn dedupe_bytes(bytes: &mut Vec<u8>, crc: &Crc32, keep: Keep) -> Result<Option<(i32, i32)>> {
+    edit_png(bytes, crc, |png| {
+        let indices: Vec<usize> = (0..png.chunks.len())
+            .filter(|i| png.chunks[*i].is(b"grAb"))
+            .collect();
+        if indices.len() < 2 {
+            return Ok(None);
+        }
+        let index = match keep {
+            Keep::First => indices[0],
+            Keep::Last => indices[indices.len() - 1],
+            Keep::Nth(n) => match indices.get(n - 1) {
+                Some(index) => *index,
+                None => {
+                    return Err(Error::MissingGrab {
+                        path: None,
+                        count: indices.len(),
+                    });
+                }
+            },
+        };
+        let kept = png.chunks[index].clone();
+        let offset = parse_grab(&kept)?;
+        png.chunks.retain(|c| !c.is(b"grAb"));
+        png.chunks.insert(indices[0], kept);
+        Ok(Some(offset))
+    })
+}
+
+///Collapses duplicate grab chunks into the one chosen by `keep` and returns its offset if there were duplicates
+pub fn dedupe(
+    path: &Path,
+    crc: &Crc32,
+    keep: Keep,
+    options: &SaveOptions,
+) -> Result<Option<(i32, i32)>> {
+    edit(path, options, |bytes| dedupe_bytes(bytes, crc, keep))
+}
+
+///Collapses duplicate grab chunks in all the specified pngs
+pub fn dedupe_all<'a>(
+    paths: impl Iterator<Item = &'a Path>,
+    crc: &Crc32,
+    keep: Keep,
+    batch: &BatchOptions,
+    options: &SaveOptions,
+    reporter: &mut impl Reporter,
+) -> Result<BatchReport> {
+    batch::run(paths, batch, reporter, |path| {
+        dedupe(path, crc, keep, options).map(Outcome::Deduped)
+    })
+}
+
+pub(crate) fn both<T>(x: Result<T>, y: Result<T>) -> Result<(T, T)> {
+    match (x, y) {
+        (Ok(x), Ok(y)) => Ok((x, y)),
+        (Err(e1), Err(e2)) => Err(Error::Expressions(vec![e1, e2])),
+        (Err(e), _) | (_, Err(e)) => Err(e),
+    }
+}
+
+fn uses_dimensions(source: &str) -> bool {
+    source.contains(['w', 'h', 'W', 'H'])
+}
+
+fn dimensions(path: &Path, crc: &Crc32) -> Result<(i32, i32)> {
+    let header = Header::open(path, crc)?;
+    Ok((header.width as i32, header.height as i32))
+}
+
+pub(crate) fn evaluate(source: &str, path: Option<&Path>, (w, h): (i32, i32)) -> Result<i32> {
+    calc::eval(source, w, h).map_err(|e| Error::Expression {
+        expression: source.into(),
+        path: path.map(Path::to_path_buf),
+        source: e,
+    })
+}
+
+///How `grab_all` applies the evaluated offsets
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GrabMode {
+    ///Uses `grab`
+    Set,
+    ///Uses `push_grab`
+    Push,
+    ///Uses `nudge`
+    Nudge,
+}
+
+///Adds grab chunks to the specified pngs using either the `grab`, `push_grab`, or `nudge` functions based on `mode`
+#[allow(clippy::too_many_arguments)]
 pub fn grab_all<'a>(
     paths: impl Iterator<Item = &'a Path>,
     crc: &Crc32,
     source_x: &str,
     source_y: &str,
-    should_push: bool,
-) -> Result<()> {
-    macro_rules! error {
-        ($($arg:tt)*) => {
-            return Err(format!($($arg)*).into())
-        };
-    }
-
-    let grab_fn = match should_push {
-        true => push_grab,
-        false => grab,
+    mode: GrabMode,
+    batch: &BatchOptions,
+    options: &SaveOptions,
+    reporter: &mut impl Reporter,
+) -> Result<BatchReport> {
+    let apply = |path: &Path, x: i32, y: i32| match mode {
+        GrabMode::Set => grab(path, crc, x, y, options).map(Outcome::Grabbed),
+        GrabMode::Push => push_grab(path, crc, x, y, options).map(|_| Outcome::Pushed((x, y))),
+        GrabMode::Nudge => nudge(path, crc, x, y, options).map(Outcome::Nudged),
     };
 
-    let width_or_height = |c| c == 'w' || c == 'h' || c == 'W' || c == 'H';
-    let get_dimensions = |path| -> Result<(i32, i32)> {
-        let (w, h) = image::open(path)?.dimensions();
-        Ok((w as i32, h as i32))
+    //Expressions without the width or height are the same for every png, so they are evaluated once
+    let constant = |source| match uses_dimensions(source) {
+        true => Ok(None),
+        false => evaluate(source, None, (0, 0)).map(Some),
     };
+    let (constant_x, constant_y) = both(constant(source_x), constant(source_y))?;
 
-    match (
-        source_x.contains(width_or_height),
-        source_y.contains(width_or_height),
-    ) {
-        (true, true) => {
-            for path in paths.into_iter() {
-                let (w, h) = get_dimensions(path)?;
-                match (calc::eval(&source_x, w, h), calc::eval(&source_y, w, h)) {
-                    (Ok(x), Ok(y)) => {
-                        grab_fn(&path, &crc, x, y)?;
-                        println!("grabbed {path:?} successfully at ({x}, {y})!");
-                    }
-                    (Err(e1), Err(e2)) => error!(
-                        "error in '{source_x}' for {path:?}: {e1}\nerror in '{source_y:}' for {path:?}: {e2}"
-                    ),
-                    (Err(e), _) => error!("error in '{source_x}' for '{path:?}': {e}"),
-                    (_, Err(e)) => error!("error in '{source_y}' for '{path:?}': {e}"),
-                }
-            }
-        }
-        (false, false) => match (calc::eval(&source_x, 0, 0), calc::eval(&source_y, 0, 0)) {
-            (Ok(x), Ok(y)) => {
-                for path in paths {
-                    grab_fn(&path, &crc, x, y)?;
-                    println!("grabbed {path:?} successfully at ({x}, {y})!");
-                }
-            }
-            (Err(e1), Err(e2)) => {
-                error!("error in '{source_x}': {e1}\nerror in '{source_y}': {e2}")
-            }
-            (Err(e), _) => error!("error in '{source_x}': {e}"),
-            (_, Err(e)) => error!("error in '{source_y}': {e}"),
-        },
-        (true, false) => match calc::eval(&source_y, 0, 0) {
-            Ok(y) => {
-                for path in paths {
-                    let (w, h) = get_dimensions(&path)?;
-                    match calc::eval(&source_x, w, h) {
-                        Ok(x) => {
-                            grab_fn(&path, &crc, x, y)?;
-                            println!("grabbed {path:?} successfully at ({x}, {y})!");
-                        }
-                        Err(e) => error!("error in '{source_x}' for {path:?}: {e}"),
-                    }
-                }
-            }
-            Err(e) => {
-                if let Some(path) = paths.into_iter().next() {
-                    let (w, h) = get_dimensions(&path)?;
-                    if let Err(e) = calc::eval(&source_x, w, h) {
-                        eprintln!("error in '{source_x}' for {path:?}: {e}");
-                    }
-                }
-                error!("error in '{source_y}': {e}");
-            }
-        },
-        (false, true) => match calc::eval(&source_x, 0, 0) {
-            Ok(x) => {
-                for path in paths {
-                    let (w, h) = get_dimensions(&path)?;
-                    match calc::eval(&source_y, w, h) {
-                        Ok(y) => {
-                            grab_fn(&path, &crc, x, y)?;
-                            println!("grabbed {path:?} successfully at ({x}, {y})!");
-                        }
-                        Err(e) => error!("error in '{source_y}' for {path:?}: {e}"),
-                    }
-                }
-            }
-            Err(e) => {
-                eprintln!("error in '{source_x}': {e}");
-                if let Some(path) = paths.into_iter().next() {
-                    let (w, h) = get_dimensions(&path)?;
-                    if let Err(e) = calc::eval(&source_y, w, h) {
-                        error!("error in '{source_y}' for {path:?}: {e}");
-                    }
-                }
-            }
-        },
-    }
+    batch::run(paths, batch, reporter, |path| {
+        let dimensions = match constant_x.is_none() || constant_y.is_none() {
+            true => dimensions(path, crc)?,
+            false => (0, 0),
+        };
+        let variable = |constant: Option<i32>, source| match constant {
+            Some(value) => Ok(value),
+            None => evaluate(source, Some(path), dimensions),
+        };
+        let (x, y) = both(
+            variable(constant_x, source_x),
+            variable(constant_y, source_y),
+        )?;
+        apply(path, x, y)
+    })
+}
 
-    Ok(())
+///Sets the offset of each row's png to its evaluated expressions, or removes its grab chunks if the row has no offset
+pub fn apply_all(
+    rows: &[Row],
+    crc: &Crc32,
+    batch: &BatchOptions,
+    options: &SaveOptions,
+    reporter: &mut impl Reporter,
+) -> Result<BatchReport> {
+    check_duplicates(rows, identity)?;
+    batch::run_items(
+        rows,
+        |row| &row.path,
+        batch,
+        reporter,
+        |row| {
+            let path = row.path.as_path();
+            let Some((source_x, source_y)) = &row.offset else {
+                return remove_grab(path, crc, options).map(Outcome::Ungrabbed);
+            };
+            let dimensions = match uses_dimensions(source_x) || uses_dimensions(source_y) {
+                true => dimensions(path, crc)?,
+                false => (0, 0),
+            };
+            let (x, y) = both(
+                evaluate(source_x, Some(path), dimensions),
+                evaluate(source_y, Some(path), dimensions),
+            )?;
+            grab(path, crc, x, y, options).map(Outcome::Grabbed)
+        },
+    )
 }
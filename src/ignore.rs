@@ -0,0 +1,138 @@
+use crate::error::*;
+use crate::glob::Pattern;
+use crate::walk::normalize;
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+///The name of the files that `Ignores` looks for in the parent directories of each path
+pub const ignore_name: &str = ".putpngignore";
+
+#[derive(Clone, Debug, PartialEq)]
+struct Rule {
+    pattern: Pattern,
+    ///Written with a leading `!` to include paths again
+    negated: bool,
+    ///Written with a trailing `/` to only match directories
+    directory_only: bool,
+}
+
+///The rules of a single ignore file, written in the same syntax as a gitignore file and relative to the directory it is in
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IgnoreFile {
+    rules: Vec<Rule>,
+}
+
+fn rule(line: &str) -> Option<Rule> {
+    let mut line = line.trim_end_matches('\r');
+    //Trailing spaces are dropped unless the last one is escaped
+    let trimmed = line.trim_end_matches(' ');
+    if trimmed.len() < line.len() && trimmed.ends_with('\\') {
+        line = &line[..trimmed.len() + 1];
+    } else {
+        line = trimmed;
+    }
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (negated, line) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let (directory_only, line) = match line.strip_suffix('/') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    if line.is_empty() {
+        return None;
+    }
+    //A slash anywhere but the end makes the pattern relative to the directory of the file, otherwise it matches a name at any depth
+    let pattern = match line.contains('/') && !line.starts_with('/') {
+        true => Pattern::new(&format!("/{line}"), false),
+        false => Pattern::new(line, false),
+    };
+    Some(Rule {
+        pattern,
+        negated,
+        directory_only,
+    })
+}
+
+impl IgnoreFile {
+    pub fn parse(text: &str) -> Self {
+        Self {
+            rules: text.lines().filter_map(rule).collect(),
+        }
+    }
+
+    ///Whether the last rule that matches the path ignores it (or None if no rule matches), with the path relative to the directory of the file
+    pub fn decide(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| (is_dir || !rule.directory_only) && rule.pattern.matches_end(path))
+            .map(|rule| !rule.negated)
+    }
+}
+
+///The ignore files of every directory that has been looked at, read once and then kept
+#[derive(Debug, Default)]
+pub struct Ignores {
+    files: HashMap<PathBuf, Option<IgnoreFile>>,
+    ///Whether each directory or any directory it is in is ignored
+    directories: HashMap<PathBuf, bool>,
+}
+
+impl Ignores {
+    fn file(&mut self, directory: &Path) -> Result<Option<&IgnoreFile>> {
+        if !self.files.contains_key(directory) {
+            let path = directory.join(ignore_name);
+            let file = match fs::read_to_string(&path) {
+                Ok(text) => Some(IgnoreFile::parse(&text)),
+                Err(e) if e.kind() == ErrorKind::NotFound => None,
+                Err(e) => return Err(Error::from(e).at(&path)),
+            };
+            self.files.insert(directory.to_path_buf(), file);
+        }
+        Ok(self.files[directory].as_ref())
+    }
+
+    //Goes through the ignore files from the root down so that the deeper ones take precedence
+    fn decide(&mut self, path: &Path, is_dir: bool) -> Result<bool> {
+        let mut ignored = false;
+        let directories: Vec<&Path> = path.ancestors().skip(1).collect();
+        for directory in directories.into_iter().rev() {
+            let relative = path.strip_prefix(directory).unwrap_or(path);
+            if let Some(decision) = self
+                .file(directory)?
+                .and_then(|f| f.decide(relative, is_dir))
+            {
+                ignored = decision;
+            }
+        }
+        Ok(ignored)
+    }
+
+    fn is_directory_ignored(&mut self, directory: &Path) -> Result<bool> {
+        if let Some(ignored) = self.directories.get(directory) {
+            return Ok(*ignored);
+        }
+        let ignored = match directory.parent() {
+            //The root itself has no name for a rule to match
+            None => false,
+            Some(parent) => self.is_directory_ignored(parent)? || self.decide(directory, true)?,
+        };
+        self.directories.insert(directory.to_path_buf(), ignored);
+        Ok(ignored)
+    }
+
+    ///Whether an ignore file in any of the parent directories ignores the path or one of the directories it is in (which cannot be undone by a `!` rule, like in git)
+    pub fn is_ignored(&mut self, path: &Path, is_dir: bool) -> Result<bool> {
+        let path = normalize(path)?;
+        match path.parent() {
+            Some(parent) if self.is_directory_ignored(parent)? => Ok(true),
+            _ => self.decide(&path, is_dir),
+        }
+    }
+}
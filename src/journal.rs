@@ -0,0 +1,145 @@
+use crate::crc::*;
+use crate::error::*;
+use crate::grab::*;
+use crate::save::*;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+///The directory that keeps the backups of every run
+pub const journal_directory: &str = ".putpng";
+
+///What is needed to restore a file to how it was before a run changed it
+pub(crate) enum Backup<'a> {
+    ///The whole original file
+    File(&'a [u8]),
+    ///Only the previous offset of the first grab chunk
+    Grab((i32, i32)),
+}
+
+///Backs up every file a single run changes into its own directory inside `.putpng`
+#[derive(Debug)]
+pub struct Journal {
+    run: PathBuf,
+    //The manifest is only created with the first backup so runs that change nothing leave nothing behind
+    manifest: Mutex<Option<(File, usize)>>,
+}
+
+impl Journal {
+    ///Starts a new run in the `.putpng` directory inside `root`
+    pub fn new(root: &Path) -> Self {
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let run = format!("{time:020}-{}", std::process::id());
+        Self {
+            run: root.join(journal_directory).join(run),
+            manifest: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn record(&self, path: &Path, backup: Backup) -> Result<()> {
+        let path = std::path::absolute(path)?;
+        let mut manifest = self.manifest.lock().unwrap_or_else(|e| e.into_inner());
+        let (file, count) = match &mut *manifest {
+            Some(manifest) => manifest,
+            None => {
+                fs::create_dir_all(&self.run)?;
+                let file = File::options()
+                    .append(true)
+                    .create_new(true)
+                    .open(self.run.join("manifest"))?;
+                manifest.insert((file, 0))
+            }
+        };
+        let line = match backup {
+            Backup::File(bytes) => {
+                let name = format!("{count}.png");
+                fs::write(self.run.join(&name), bytes)?;
+                format!("file\t{name}\t{}\n", path.display())
+            }
+            Backup::Grab((x, y)) => format!("grab\t{x}\t{y}\t{}\n", path.display()),
+        };
+        file.write_all(line.as_bytes())?;
+        file.sync_data()?;
+        *count += 1;
+        Ok(())
+    }
+}
+
+enum Entry {
+    File(PathBuf, PathBuf),
+    Grab(PathBuf, (i32, i32)),
+}
+
+fn parse_manifest(manifest: &Path, run: &Path) -> Result<Vec<Entry>> {
+    let text = fs::read_to_string(manifest).map_err(|e| Error::from(e).at(manifest))?;
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let fields: Vec<&str> = line.splitn(4, '\t').collect();
+            let entry = match fields[..] {
+                ["file", name, path] => Some(Entry::File(run.join(name), path.into())),
+                ["grab", x, y, path] => match (x.parse(), y.parse()) {
+                    (Ok(x), Ok(y)) => Some(Entry::Grab(path.into(), (x, y))),
+                    _ => None,
+                },
+                _ => None,
+            };
+            entry.ok_or(Error::Manifest {
+                path: Some(manifest.to_path_buf()),
+                line: i + 1,
+            })
+        })
+        .collect()
+}
+
+fn last_run(root: &Path) -> Result<Option<PathBuf>> {
+    let runs = match fs::read_dir(root.join(journal_directory)) {
+        Ok(runs) => runs,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut runs = runs
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    runs.retain(|run| run.join("manifest").is_file());
+    runs.sort();
+    Ok(runs.pop())
+}
+
+///Restores every file changed by the last run journaled in `root` and returns their paths (the run is removed once all of them are restored)
+pub fn undo(root: &Path, crc: &Crc32, options: &SaveOptions) -> Result<Vec<PathBuf>> {
+    let Some(run) = last_run(root)? else {
+        return Ok(vec![]);
+    };
+    let options = SaveOptions {
+        journal: None,
+        ..options.clone()
+    };
+    let entries = parse_manifest(&run.join("manifest"), &run)?;
+    let mut restored = vec![];
+    //Going backwards restores a file that was changed twice to its first backup
+    for entry in entries.into_iter().rev() {
+        match entry {
+            Entry::File(backup, path) => {
+                let bytes = fs::read(&backup).map_err(|e| Error::from(e).at(&backup))?;
+                replace(&path, &bytes, &options)?;
+                restored.push(path);
+            }
+            Entry::Grab(path, (x, y)) => {
+                grab(&path, crc, x, y, &options)?;
+                restored.push(path);
+            }
+        }
+    }
+    if !options.dry_run {
+        fs::remove_dir_all(&run).map_err(|e| Error::from(e).at(&run))?;
+    }
+    restored.reverse();
+    restored.dedup();
+    Ok(restored)
+}
@@ -0,0 +1,205 @@
+//Just enough of a json reader for the offset manifests (numbers are kept as their text)
+
+use crate::cursor::{Cursor, Error};
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    ///Each value is paired with the line it starts on
+    Array(Vec<(Value, usize)>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub(crate) fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(fields) => fields.iter().find(|f| f.0 == key).map(|f| &f.1),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    cursor: Cursor<'a>,
+}
+
+impl Parser<'_> {
+    //Reads the four hex digits after `\u` (and the low surrogate after them if there is a high one), leaving the index on the last digit
+    fn unicode_escape(&mut self) -> Option<char> {
+        let code = self.cursor.hex(self.cursor.index + 1)?;
+        self.cursor.index += 4;
+        if !(0xd800..0xdc00).contains(&code) {
+            return char::from_u32(code);
+        }
+        if self
+            .cursor
+            .text
+            .get(self.cursor.index + 1..self.cursor.index + 3)
+            != Some(b"\\u")
+        {
+            return None;
+        }
+        let low = self
+            .cursor
+            .hex(self.cursor.index + 3)
+            .filter(|low| (0xdc00..0xe000).contains(low))?;
+        self.cursor.index += 6;
+        char::from_u32(0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.cursor.get().is_some_and(|b| b.is_ascii_whitespace()) {
+            self.cursor.index += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.cursor.get()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), Error> {
+        match self.peek() {
+            Some(b) if b == byte => {
+                self.cursor.index += 1;
+                Ok(())
+            }
+            _ => self.cursor.error(&format!("expected '{}'", byte as char)),
+        }
+    }
+
+    fn keyword(&mut self, word: &str, value: Value) -> Result<Value, Error> {
+        match self.cursor.text[self.cursor.index..].starts_with(word.as_bytes()) {
+            true => {
+                self.cursor.index += word.len();
+                Ok(value)
+            }
+            false => self.cursor.error("unexpected character"),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, Error> {
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'n') => self.keyword("null", Value::Null),
+            Some(b't') => self.keyword("true", Value::Bool(true)),
+            Some(b'f') => self.keyword("false", Value::Bool(false)),
+            Some(b'-' | b'0'..=b'9') => Ok(self.number()),
+            Some(_) => self.cursor.error("unexpected character"),
+            None => self.cursor.error("unexpected end"),
+        }
+    }
+
+    fn number(&mut self) -> Value {
+        let start = self.cursor.index;
+        while self
+            .cursor
+            .get()
+            .is_some_and(|b| b.is_ascii_digit() || b"+-.eE".contains(&b))
+        {
+            self.cursor.index += 1;
+        }
+        Value::Number(String::from_utf8_lossy(&self.cursor.text[start..self.cursor.index]).into())
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        self.expect(b'"')?;
+        let mut bytes = vec![];
+        loop {
+            match self.cursor.get() {
+                None => return self.cursor.error("unterminated string"),
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    self.cursor.index += 1;
+                    let escaped = match self.cursor.get() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => match self.unicode_escape() {
+                            Some(c) => c,
+                            None => return self.cursor.error("invalid unicode escape"),
+                        },
+                        _ => return self.cursor.error("invalid escape"),
+                    };
+                    bytes.extend_from_slice(escaped.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                Some(b) => bytes.push(b),
+            }
+            self.cursor.index += 1;
+        }
+        self.cursor.index += 1;
+        String::from_utf8(bytes).or_else(|_| self.cursor.error("invalid utf-8"))
+    }
+
+    fn array(&mut self) -> Result<Value, Error> {
+        self.expect(b'[')?;
+        self.cursor.enter()?;
+        let mut values = vec![];
+        if self.peek() == Some(b']') {
+            self.cursor.index += 1;
+            self.cursor.leave();
+            return Ok(Value::Array(values));
+        }
+        loop {
+            self.skip_whitespace();
+            let line = self.cursor.line();
+            values.push((self.value()?, line));
+            match self.peek() {
+                Some(b',') => self.cursor.index += 1,
+                Some(b']') => {
+                    self.cursor.index += 1;
+                    self.cursor.leave();
+                    return Ok(Value::Array(values));
+                }
+                _ => return self.cursor.error("expected ',' or ']'"),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, Error> {
+        self.expect(b'{')?;
+        self.cursor.enter()?;
+        let mut fields = vec![];
+        if self.peek() == Some(b'}') {
+            self.cursor.index += 1;
+            self.cursor.leave();
+            return Ok(Value::Object(fields));
+        }
+        loop {
+            let key = self.string()?;
+            self.expect(b':')?;
+            fields.push((key, self.value()?));
+            match self.peek() {
+                Some(b',') => self.cursor.index += 1,
+                Some(b'}') => {
+                    self.cursor.index += 1;
+                    self.cursor.leave();
+                    return Ok(Value::Object(fields));
+                }
+                _ => return self.cursor.error("expected ',' or '}'"),
+            }
+        }
+    }
+}
+
+///Parses the text as a single json value
+pub(crate) fn parse(text: &str) -> Result<Value, Error> {
+    let mut parser = Parser {
+        cursor: Cursor::new(text),
+    };
+    let value = parser.value()?;
+    match parser.peek() {
+        None => Ok(value),
+        Some(_) => parser.cursor.error("unexpected data after the end"),
+    }
+}
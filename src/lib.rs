@@ -7,19 +7,129 @@
 //!
 //! fn main() {
 //!     let crc = Crc32::new();
+//!     let options = SaveOptions::default();
 //!     let path = "sample.png";
 //!
-//!     grab(path, &crc, 30, 20).unwrap();
-//!     crop(path, &crc).unwrap();
+//!     grab(path, &crc, 30, 20, &options).unwrap();
+//!     crop(path, &crc, false, &options).unwrap();
+//! }
+//! ```
+//!
+//! Pngs held in memory can be edited with the `_bytes` functions instead
+//! ```rust,ignore
+//! use putpng::prelude::*;
+//!
+//! fn main() {
+//!     let crc = Crc32::new();
+//!     let mut bytes = std::fs::read("sample.png").unwrap();
+//!
+//!     set_grab_bytes(&mut bytes, &crc, 30, 20).unwrap();
+//!     assert_eq!(read_grab_from(&mut bytes.as_slice(), &crc).unwrap(), Some((30, 20)));
+//!     crop_bytes(&mut bytes, &crc, false).unwrap();
 //! }
 //! ```
 
 #![allow(nonstandard_style)]
-mod calc;
+pub mod batch;
+pub mod calc;
+pub mod config;
 pub mod crc;
 pub mod crop;
+mod cursor;
+pub mod error;
+pub mod glob;
 pub mod grab;
+pub mod ignore;
+pub mod journal;
+mod json;
+pub mod png;
 pub mod prelude;
+pub mod record;
+pub mod save;
+mod toml;
+pub mod verify;
+pub mod walk;
+
+pub use error::Error;
+
+#[cfg(test)]
+mod temp_dir {
+    use std::ops::Deref;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    ///A directory that only one test uses, removed with everything in it when dropped
+    pub(crate) struct TempDir(PathBuf);
+
+    impl TempDir {
+        pub(crate) fn new(name: &str) -> Self {
+            static count: AtomicUsize = AtomicUsize::new(0);
+            let id = count.fetch_add(1, Ordering::Relaxed);
+            let path =
+                std::env::temp_dir().join(format!("putpng_{name}.{}.{id}", std::process::id()));
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        ///A new directory holding a single file with the bytes
+        pub(crate) fn with_file(name: &str, file: &str, bytes: &[u8]) -> (Self, PathBuf) {
+            let directory = Self::new(name);
+            let path = directory.write(file, bytes);
+            (directory, path)
+        }
+
+        ///A new directory holding a single copy of the sample png
+        pub(crate) fn with_png(name: &str, file: &str) -> (Self, PathBuf) {
+            Self::with_file(name, file, &crate::png_tests::sample())
+        }
+
+        ///Writes the bytes to the file (and creates the directories it is in) and returns its path
+        pub(crate) fn write(&self, file: &str, bytes: &[u8]) -> PathBuf {
+            let path = self.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, bytes).unwrap();
+            path
+        }
+
+        ///Writes a copy of the sample png to the file and returns its path
+        pub(crate) fn png(&self, file: &str) -> PathBuf {
+            self.write(file, &crate::png_tests::sample())
+        }
+    }
+
+    ///The files relative to the root with `/` between their components, so that walks can be compared on every platform
+    pub(crate) fn relative(root: &Path, files: &[PathBuf]) -> Vec<String> {
+        files
+            .iter()
+            .map(|f| {
+                f.strip_prefix(root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .replace('\\', "/")
+            })
+            .collect()
+    }
+
+    impl Deref for TempDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl AsRef<Path> for TempDir {
+        fn as_ref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+}
 
 #[cfg(test)]
 mod calc_tests {
@@ -144,4 +254,1451 @@ mod calc_tests {
     fn divide_by_zero() {
         assert_eq!(eval("1 / 0", 0, 0), Err(Error::DivideByZero));
     }
+
+    #[test]
+    fn integer_too_large() {
+        assert_eq!(
+            eval("99999999999", 0, 0),
+            Err(Error::IntegerTooLarge("99999999999".into()))
+        );
+        assert_eq!(eval("2147483647", 0, 0), Ok(i32::MAX));
+    }
+
+    #[test]
+    fn overflow() {
+        assert_eq!(eval("2147483647 + 1", 0, 0), Err(Error::Overflow));
+        assert_eq!(eval("-2147483647 - 2", 0, 0), Err(Error::Overflow));
+        assert_eq!(eval("w * h", 65536, 65536), Err(Error::Overflow));
+        assert_eq!(eval("-w / -1", i32::MAX, 0), Ok(i32::MAX));
+        assert_eq!(eval("(-2147483647 - 1) / -1", 0, 0), Err(Error::Overflow));
+        assert_eq!(eval("-(-2147483647 - 1)", 0, 0), Err(Error::Overflow));
+    }
+}
+
+#[cfg(test)]
+mod png_tests {
+    use crate::crc::*;
+    use crate::png::*;
+    use std::io::Cursor;
+
+    pub fn sample() -> Vec<u8> {
+        let mut bytes = vec![];
+        image::RgbaImage::new(4, 4)
+            .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    fn read_error(bytes: &[u8]) -> Error {
+        match PngFile::read(&mut &bytes[..], &Crc32::new()) {
+            Err(crate::Error::Png { source, .. }) => source,
+            result => panic!("expected a png error, found {result:?}"),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = sample();
+        let png = PngFile::read(&mut bytes.as_slice(), &Crc32::new()).unwrap();
+        let mut written = vec![];
+        png.write(&mut written).unwrap();
+        assert_eq!(written, bytes);
+    }
+
+    #[test]
+    fn chunk_order() {
+        let png = PngFile::read(&mut sample().as_slice(), &Crc32::new()).unwrap();
+        assert_eq!(png.position(b"IHDR"), Some(0));
+        assert_eq!(png.chunks.last().map(|c| c.kind), Some(*b"IEND"));
+    }
+
+    #[test]
+    fn chunk_crc() {
+        let crc = Crc32::new();
+        let png = PngFile::read(&mut sample().as_slice(), &crc).unwrap();
+        let ihdr = png.find(b"IHDR").unwrap();
+        assert_eq!(Chunk::new(&crc, b"IHDR", ihdr.data.clone()).crc, ihdr.crc);
+    }
+
+    #[test]
+    fn not_png() {
+        assert_eq!(read_error(b"GIF89a"), Error::NotPng);
+        assert_eq!(read_error(b"\xff\xd8\xff\xe0\0\x10JFIF\0"), Error::NotPng);
+    }
+
+    #[test]
+    fn header_crc() {
+        let mut bytes = sample();
+        bytes[29] ^= 0xff;
+        assert_eq!(read_error(&bytes), Error::HeaderCrc);
+    }
+
+    #[test]
+    fn header_length() {
+        let mut bytes = sample();
+        bytes[11] = 12;
+        assert_eq!(read_error(&bytes), Error::HeaderLength(12));
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = sample();
+        assert_eq!(read_error(&bytes[..bytes.len() - 6]), Error::Truncated);
+    }
+
+    #[test]
+    fn header() {
+        let crc = Crc32::new();
+        let bytes = sample();
+        let expected = Header {
+            width: 4,
+            height: 4,
+            bit_depth: 8,
+            color_type: 6,
+            interlaced: false,
+        };
+        //Only the signature and IHDR are needed
+        assert_eq!(Header::read(&mut &bytes[..33], &crc).unwrap(), expected);
+        let png = PngFile::read(&mut bytes.as_slice(), &crc).unwrap();
+        assert_eq!(png.header(), expected);
+        assert_eq!(expected.color_name(), "truecolor with alpha");
+    }
+}
+
+#[cfg(test)]
+mod verify_tests {
+    use crate::crc::*;
+    use crate::png::*;
+    use crate::png_tests::sample;
+    use crate::verify::*;
+
+    fn with_chunk(bytes: &[u8], chunk: Chunk) -> Vec<u8> {
+        let mut png = PngFile::read(&mut &bytes[..], &Crc32::new()).unwrap();
+        png.chunks.insert(1, chunk);
+        let mut written = vec![];
+        png.write(&mut written).unwrap();
+        written
+    }
+
+    #[test]
+    fn valid() {
+        assert_eq!(verify_bytes(&sample(), &Crc32::new()), vec![]);
+    }
+
+    #[test]
+    fn not_png() {
+        assert_eq!(verify_bytes(b"hello", &Crc32::new()), vec![Problem::NotPng]);
+    }
+
+    #[test]
+    fn crc_mismatch() {
+        let mut bytes = sample();
+        bytes[29] ^= 0xff;
+        assert!(matches!(
+            verify_bytes(&bytes, &Crc32::new())[..],
+            [Problem::CrcMismatch { offset: 8, .. }]
+        ));
+    }
+
+    #[test]
+    fn missing_end() {
+        let bytes = sample();
+        assert_eq!(
+            verify_bytes(&bytes[..bytes.len() - 12], &Crc32::new()),
+            vec![Problem::MissingEnd]
+        );
+    }
+
+    #[test]
+    fn trailing_data() {
+        let bytes = [sample(), b"junk".to_vec()].concat();
+        assert_eq!(
+            verify_bytes(&bytes, &Crc32::new()),
+            vec![Problem::TrailingData(4)]
+        );
+    }
+
+    #[test]
+    fn duplicate_grab() {
+        let crc = Crc32::new();
+        let grab = Chunk::new(&crc, b"grAb", vec![0; 8]);
+        let bytes = with_chunk(&with_chunk(&sample(), grab.clone()), grab);
+        assert_eq!(verify_bytes(&bytes, &crc), vec![Problem::DuplicateGrab(2)]);
+    }
+
+    #[test]
+    fn bad_length() {
+        let crc = Crc32::new();
+        let bytes = with_chunk(&sample(), Chunk::new(&crc, b"grAb", vec![0; 4]));
+        assert!(matches!(
+            verify_bytes(&bytes, &crc)[..],
+            [Problem::BadLength {
+                length: 4,
+                expected: 8,
+                ..
+            }]
+        ));
+    }
+}
+
+#[cfg(test)]
+mod save_tests {
+    use crate::save::*;
+    use crate::temp_dir::TempDir;
+    use std::fs;
+
+    #[test]
+    fn replace_contents() {
+        let (directory, path) = TempDir::with_file("save_tests_replace", "sprite.png", b"old");
+        replace(&path, b"new", &SaveOptions::default()).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn keep_modified() {
+        let (_directory, path) =
+            TempDir::with_file("save_tests_keep_modified", "sprite.png", b"old");
+        let modified = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1 << 30);
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        let options = SaveOptions {
+            keep_modified: true,
+            ..Default::default()
+        };
+        replace(&path, b"new", &options).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), modified);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn replace_through_link() {
+        let (directory, path) =
+            TempDir::with_file("save_tests_replace_through_link", "sprite.png", b"old");
+        let link = directory.join("link.png");
+        std::os::unix::fs::symlink("sprite.png", &link).unwrap();
+        replace(&link, b"new", &SaveOptions::default()).unwrap();
+        assert!(
+            fs::symlink_metadata(&link)
+                .unwrap()
+                .file_type()
+                .is_symlink()
+        );
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn dry_run() {
+        let crc = crate::crc::Crc32::new();
+        let (_directory, path) = TempDir::with_png("save_tests_dry_run", "sprite.png");
+        let options = SaveOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+        let change = crate::grab::grab(&path, &crc, 3, 4, &options).unwrap();
+        assert!(change.is_changed());
+        assert!(crate::crop::crop(&path, &crc, true, &options).is_ok());
+        assert_eq!(fs::read(&path).unwrap(), crate::png_tests::sample());
+    }
+}
+
+#[cfg(test)]
+mod grab_tests {
+    use crate::crc::*;
+    use crate::grab::*;
+    use crate::png_tests::sample;
+    use crate::save::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn grab_and_read() {
+        let (crc, options) = (Crc32::new(), SaveOptions::default());
+        let (_directory, path) = TempDir::with_png("grab_tests_grab_and_read", "sample.png");
+        assert_eq!(read_grab(&path, &crc).unwrap(), None);
+        grab(&path, &crc, 16, -32, &options).unwrap();
+        grab(&path, &crc, 8, 4, &options).unwrap();
+        assert_eq!(read_grab(&path, &crc).unwrap(), Some((8, 4)));
+    }
+
+    #[test]
+    fn grab_unchanged() {
+        let (crc, options) = (Crc32::new(), SaveOptions::default());
+        let (_directory, path) = TempDir::with_png("grab_tests_grab_unchanged", "sample.png");
+        assert!(grab(&path, &crc, 5, 5, &options).unwrap().is_changed());
+        let bytes = std::fs::read(&path).unwrap();
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        let change = grab(&path, &crc, 5, 5, &options).unwrap();
+        assert_eq!(change.before, Some((5, 5)));
+        assert!(!change.is_changed());
+        assert!(!nudge(&path, &crc, 0, 0, &options).unwrap().is_changed());
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().modified().unwrap(),
+            modified
+        );
+    }
+
+    #[test]
+    fn remove_pushed_grabs() {
+        let (crc, options) = (Crc32::new(), SaveOptions::default());
+        let (_directory, path) = TempDir::with_png("grab_tests_remove_pushed_grabs", "sample.png");
+        push_grab(&path, &crc, 1, 2, &options).unwrap();
+        push_grab(&path, &crc, 3, 4, &options).unwrap();
+        assert_eq!(remove_grab(&path, &crc, &options).unwrap(), 2);
+        assert_eq!(read_grab(&path, &crc).unwrap(), None);
+        assert_eq!(std::fs::read(&path).unwrap(), sample());
+    }
+
+    #[test]
+    fn dedupe_keeps_chosen() {
+        let (crc, options) = (Crc32::new(), SaveOptions::default());
+        let (_directory, path) = TempDir::with_png("grab_tests_dedupe_keeps_chosen", "sample.png");
+        for offset in 1..=3 {
+            push_grab(&path, &crc, offset, offset, &options).unwrap();
+        }
+        assert_eq!(
+            read_grabs(&path, &crc).unwrap(),
+            vec![(3, 3), (2, 2), (1, 1)]
+        );
+        assert_eq!(
+            dedupe(&path, &crc, Keep::Nth(2), &options).unwrap(),
+            Some((2, 2))
+        );
+        assert_eq!(read_grabs(&path, &crc).unwrap(), vec![(2, 2)]);
+        assert_eq!(dedupe(&path, &crc, Keep::Last, &options).unwrap(), None);
+    }
+
+    #[test]
+    fn nudge_offsets() {
+        let (crc, options) = (Crc32::new(), SaveOptions::default());
+        let (_directory, path) = TempDir::with_png("grab_tests_nudge_offsets", "sample.png");
+        assert_eq!(nudge(&path, &crc, 0, -2, &options).unwrap().after, (0, -2));
+        assert_eq!(nudge(&path, &crc, 3, -2, &options).unwrap().after, (3, -4));
+        assert_eq!(read_grabs(&path, &crc).unwrap(), vec![(3, -4)]);
+    }
+
+    #[test]
+    fn nudge_out_of_range() {
+        let (crc, options) = (Crc32::new(), SaveOptions::default());
+        let (_directory, path) = TempDir::with_png("grab_tests_nudge_out_of_range", "sample.png");
+        grab(&path, &crc, i32::MAX, 0, &options).unwrap();
+        match nudge(&path, &crc, 1, 0, &options) {
+            Err(e @ crate::Error::Overflow { .. }) => assert_eq!(e.path(), Some(path.as_path())),
+            result => panic!("expected an overflow error, found {result:?}"),
+        }
+        assert_eq!(read_grab(&path, &crc).unwrap(), Some((i32::MAX, 0)));
+    }
+
+    #[test]
+    fn keep_from_str() {
+        assert_eq!("first".parse(), Ok(Keep::First));
+        assert_eq!("last".parse(), Ok(Keep::Last));
+        assert_eq!("2".parse(), Ok(Keep::Nth(2)));
+        assert!("0".parse::<Keep>().is_err());
+        assert!("middle".parse::<Keep>().is_err());
+    }
+}
+
+#[cfg(test)]
+mod crop_tests {
+    use crate::crc::*;
+    use crate::crop::*;
+    use crate::grab::*;
+    use crate::png::*;
+    use crate::save::*;
+    use crate::temp_dir::TempDir;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    //An 8x6 image with two pixels in it and a text chunk
+    fn sprite_file(name: &str) -> (TempDir, PathBuf) {
+        let crc = Crc32::new();
+        let mut image = image::RgbaImage::new(8, 6);
+        image.put_pixel(2, 1, image::Rgba([255, 0, 0, 255]));
+        image.put_pixel(4, 3, image::Rgba([0, 255, 0, 255]));
+        let mut bytes = vec![];
+        image
+            .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)
+            .unwrap();
+        let mut png = PngFile::read(&mut bytes.as_slice(), &crc).unwrap();
+        png.chunks
+            .insert(1, Chunk::new(&crc, b"tEXt", b"Author\0putpng".to_vec()));
+        TempDir::with_file(&format!("crop_tests_{name}"), "sprite.png", &png.to_bytes())
+    }
+
+    #[test]
+    fn crop_keeps_ancillary() {
+        let (crc, options) = (Crc32::new(), SaveOptions::default());
+        let (_directory, path) = sprite_file("crop_keeps_ancillary");
+        grab(&path, &crc, 10, 10, &options).unwrap();
+        crop(&path, &crc, false, &options).unwrap();
+        assert_eq!(image::image_dimensions(&path).unwrap(), (3, 3));
+        assert_eq!(read_grab(&path, &crc).unwrap(), Some((8, 9)));
+        let png = PngFile::open(&path, &crc).unwrap();
+        assert_eq!(png.find(b"tEXt").unwrap().data, b"Author\0putpng");
+    }
+
+    #[test]
+    fn crop_unchanged() {
+        let (crc, options) = (Crc32::new(), SaveOptions::default());
+        let (_directory, path) = sprite_file("crop_unchanged");
+        assert!(crop(&path, &crc, false, &options).unwrap().is_changed());
+        let bytes = std::fs::read(&path).unwrap();
+        let change = crop(&path, &crc, false, &options).unwrap();
+        assert_eq!(change.bounds, (0, 0, 3, 3));
+        assert!(!change.is_changed());
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+        assert!(crop(&path, &crc, true, &options).unwrap().is_changed());
+    }
+
+    #[test]
+    fn crop_strip() {
+        let (crc, options) = (Crc32::new(), SaveOptions::default());
+        let (_directory, path) = sprite_file("crop_strip");
+        crop(&path, &crc, true, &options).unwrap();
+        assert_eq!(read_grab(&path, &crc).unwrap(), Some((-2, -1)));
+        let png = PngFile::open(&path, &crc).unwrap();
+        assert_eq!(png.find(b"tEXt"), None);
+    }
+
+    #[test]
+    fn crop_out_of_range() {
+        let (crc, options) = (Crc32::new(), SaveOptions::default());
+        let (_directory, path) = sprite_file("crop_out_of_range");
+        grab(&path, &crc, i32::MIN, 0, &options).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        assert!(matches!(
+            crop(&path, &crc, false, &options),
+            Err(crate::Error::Overflow { .. })
+        ));
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+    }
+
+    fn indexed_file(directory: &TempDir, depth: ::png::BitDepth) -> PathBuf {
+        let (width, height, bits) = (9u32, 7u32, depth as usize);
+        let mut data = vec![];
+        for y in 0..height {
+            let mut row = vec![0u8; (width as usize * bits).div_ceil(8)];
+            for x in 0..width {
+                let index = match (x, y) {
+                    (3..=5, 2..=4) if bits == 1 => 1,
+                    (3..=5, 2..=4) => (x + y) as u8 % 2 + 1,
+                    _ => 0,
+                };
+                let bit = x as usize * bits;
+                row[bit / 8] |= index << (8 - bits - bit % 8);
+            }
+            data.extend(row);
+        }
+        let mut bytes = vec![];
+        let mut encoder = ::png::Encoder::new(&mut bytes, width, height);
+        encoder.set_color(::png::ColorType::Indexed);
+        encoder.set_depth(depth);
+        encoder.set_palette(vec![0, 0, 0, 255, 0, 0, 0, 0, 255]);
+        encoder.set_trns(vec![0]);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&data).unwrap();
+        writer.finish().unwrap();
+        directory.write(&format!("indexed_{}.png", depth as u8), &bytes)
+    }
+
+    #[test]
+    fn crop_keeps_palette() {
+        use ::png::BitDepth::*;
+        let (crc, options) = (Crc32::new(), SaveOptions::default());
+        let directory = TempDir::new("crop_tests_crop_keeps_palette");
+        for depth in [One, Two, Four, Eight] {
+            let path = indexed_file(&directory, depth);
+            let original = image::open(&path).unwrap().to_rgba8();
+            let palette = PngFile::open(&path, &crc).unwrap().find(b"PLTE").cloned();
+            crop(&path, &crc, false, &options).unwrap();
+
+            let png = PngFile::open(&path, &crc).unwrap();
+            assert_eq!(png.chunks[0].data[8..10], [depth as u8, 3]);
+            assert_eq!(png.find(b"PLTE").cloned(), palette);
+            assert_eq!(png.find(b"tRNS").unwrap().data, vec![0]);
+            let cropped = image::open(&path).unwrap().to_rgba8();
+            assert_eq!(cropped.dimensions(), (3, 3));
+            for (x, y, pixel) in cropped.enumerate_pixels() {
+                assert_eq!(pixel, original.get_pixel(x + 3, y + 2));
+            }
+            assert_eq!(read_grab(&path, &crc).unwrap(), Some((-3, -2)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod error_tests {
+    use crate::Error;
+    use crate::batch::*;
+    use crate::calc;
+    use crate::crc::*;
+    use crate::grab::*;
+    use crate::save::*;
+    use crate::temp_dir::TempDir;
+    use std::path::Path;
+
+    #[test]
+    fn missing_file() {
+        let path = Path::new("putpng_error_tests_missing.png");
+        match read_grab(path, &Crc32::new()) {
+            Err(e @ Error::Io { .. }) => assert_eq!(e.path(), Some(path)),
+            result => panic!("expected an io error, found {result:?}"),
+        }
+    }
+
+    #[test]
+    fn not_png() {
+        let (_directory, path) = TempDir::with_file("error_tests_not_png", "not_png.png", b"hello");
+        let result = grab(&path, &Crc32::new(), 1, 2, &SaveOptions::default());
+        assert!(matches!(
+            result,
+            Err(Error::Png {
+                source: crate::png::Error::NotPng,
+                ..
+            })
+        ));
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn bad_expressions() {
+        let paths = [Path::new("putpng_error_tests_unused.png")];
+        let (crc, options) = (Crc32::new(), SaveOptions::default());
+        let batch = BatchOptions::default();
+        match grab_all(
+            paths.into_iter(),
+            &crc,
+            "1 +",
+            "2 / 0",
+            GrabMode::Set,
+            &batch,
+            &options,
+            &mut (),
+        ) {
+            Err(Error::Expressions(errors)) => assert!(matches!(
+                errors[..],
+                [
+                    Error::Expression {
+                        source: calc::Error::DanglingOperator('+'),
+                        ..
+                    },
+                    Error::Expression {
+                        source: calc::Error::DivideByZero,
+                        ..
+                    }
+                ]
+            )),
+            result => panic!("expected expression errors, found {result:?}"),
+        }
+    }
+
+    #[test]
+    fn batch_continues() {
+        let (crc, options) = (Crc32::new(), SaveOptions::default());
+        let (directory, path) = TempDir::with_png("error_tests_batch_continues", "a.png");
+        let missing = directory.join("missing.png");
+        let paths = [missing.as_path(), &path];
+        let report = grab_all(
+            paths.into_iter(),
+            &crc,
+            "1",
+            "2",
+            GrabMode::Set,
+            &BatchOptions::default(),
+            &options,
+            &mut (),
+        )
+        .unwrap();
+        assert_eq!(report.succeeded, vec![path.clone()]);
+        assert!(report.skipped.is_empty());
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].path(), Some(missing.as_path()));
+
+        let batch = BatchOptions {
+            fail_fast: true,
+            ..Default::default()
+        };
+        let result = remove_grab_all(paths.into_iter(), &crc, &batch, &options, &mut ());
+        assert!(matches!(result, Err(Error::Io { .. })));
+        assert_eq!(read_grab(&path, &crc).unwrap(), Some((1, 2)));
+    }
+}
+
+#[cfg(test)]
+mod batch_tests {
+    use crate::Error;
+    use crate::batch::*;
+    use crate::crc::*;
+    use crate::grab::*;
+    use crate::save::*;
+    use crate::temp_dir::TempDir;
+    use std::path::{Path, PathBuf};
+
+    #[derive(Default)]
+    struct Recorder {
+        processed: Vec<(PathBuf, Outcome)>,
+        failed: usize,
+    }
+
+    impl Reporter for Recorder {
+        fn processed(&mut self, path: &Path, outcome: &Outcome) {
+            self.processed.push((path.to_path_buf(), *outcome));
+        }
+
+        fn failed(&mut self, _error: &Error) {
+            self.failed += 1;
+        }
+    }
+
+    #[test]
+    fn reports_outcomes() {
+        let (crc, options) = (Crc32::new(), SaveOptions::default());
+        let (directory, path) = TempDir::with_png("batch_tests_reports_outcomes", "a.png");
+        let missing = directory.join("missing.png");
+        let paths = [path.as_path(), &missing];
+        let mut recorder = Recorder::default();
+        let batch = BatchOptions::default();
+        grab_all(
+            paths.into_iter(),
+            &crc,
+            "w",
+            "-1",
+            GrabMode::Set,
+            &batch,
+            &options,
+            &mut recorder,
+        )
+        .unwrap();
+        remove_grab_all(paths.into_iter(), &crc, &batch, &options, &mut recorder).unwrap();
+        assert_eq!(
+            recorder.processed,
+            vec![
+                (
+                    path.clone(),
+                    Outcome::Grabbed(GrabChange {
+                        before: None,
+                        after: (4, -1)
+                    })
+                ),
+                (path.clone(), Outcome::Ungrabbed(1))
+            ]
+        );
+        assert_eq!(recorder.failed, 2);
+    }
+
+    #[test]
+    fn ordered_across_jobs() {
+        let (crc, options) = (Crc32::new(), SaveOptions::default());
+        let directory = TempDir::new("batch_tests_ordered_across_jobs");
+        let paths: Vec<PathBuf> = (0..16)
+            .map(|i| directory.png(&format!("{i}.png")))
+            .collect();
+        let mut recorder = Recorder::default();
+        let batch = BatchOptions {
+            jobs: 4,
+            ..Default::default()
+        };
+        let report = grab_all(
+            paths.iter().map(PathBuf::as_path),
+            &crc,
+            "1",
+            "1",
+            GrabMode::Push,
+            &batch,
+            &options,
+            &mut recorder,
+        )
+        .unwrap();
+        assert_eq!(report.succeeded, paths);
+        let reported: Vec<PathBuf> = recorder.processed.into_iter().map(|(p, _)| p).collect();
+        assert_eq!(reported, paths);
+        for path in &paths {
+            assert_eq!(read_grabs(path, &crc).unwrap(), vec![(1, 1)]);
+        }
+    }
+
+    #[test]
+    fn duplicates() {
+        let (crc, options) = (Crc32::new(), SaveOptions::default());
+        let (directory, path) = TempDir::with_png("batch_tests_duplicates", "a.png");
+        let same = directory.join(".").join("a.png");
+        let paths = [path.as_path(), &path, &same, &path];
+        let batch = BatchOptions {
+            jobs: 3,
+            ..Default::default()
+        };
+        let report = grab_all(
+            paths.into_iter(),
+            &crc,
+            "1",
+            "1",
+            GrabMode::Push,
+            &batch,
+            &options,
+            &mut (),
+        )
+        .unwrap();
+        assert_eq!(report.succeeded, vec![path.clone()]);
+        assert!(report.failed.is_empty());
+        assert_eq!(read_grabs(&path, &crc).unwrap(), vec![(1, 1)]);
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn fail_fast_across_jobs() {
+        let (crc, options) = (Crc32::new(), SaveOptions::default());
+        let directory = TempDir::new("batch_tests_fail_fast_across_jobs");
+        let files: Vec<PathBuf> = ["a.png", "b.png"]
+            .iter()
+            .map(|name| {
+                let path = directory.png(name);
+                grab(&path, &crc, 1, 2, &options).unwrap();
+                path
+            })
+            .collect();
+        let missing = directory.join("missing.png");
+        let paths = [missing.as_path(), &files[0], &files[1]];
+        let mut recorder = Recorder::default();
+        let batch = BatchOptions {
+            fail_fast: true,
+            jobs: 3,
+        };
+        let result = remove_grab_all(paths.into_iter(), &crc, &batch, &options, &mut recorder);
+        assert!(matches!(result, Err(Error::Io { .. })));
+        assert!(recorder.processed.is_empty());
+        for path in &files {
+            assert_eq!(read_grab(path, &crc).unwrap(), Some((1, 2)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod bytes_tests {
+    use crate::crc::*;
+    use crate::crop::*;
+    use crate::grab::*;
+    use crate::png_tests::sample;
+    use std::io::Cursor;
+
+    #[test]
+    fn grab_in_memory() {
+        let crc = Crc32::new();
+        let mut bytes = sample();
+        assert_eq!(read_grab_from(&mut bytes.as_slice(), &crc).unwrap(), None);
+        assert!(
+            set_grab_bytes(&mut bytes, &crc, 3, -7)
+                .unwrap()
+                .is_changed()
+        );
+        let grabbed = bytes.clone();
+        assert!(
+            !set_grab_bytes(&mut bytes, &crc, 3, -7)
+                .unwrap()
+                .is_changed()
+        );
+        assert_eq!(bytes, grabbed);
+        nudge_bytes(&mut bytes, &crc, 1, 1).unwrap();
+        push_grab_bytes(&mut bytes, &crc, 9, 9).unwrap();
+        assert_eq!(
+            read_grabs_from(&mut Cursor::new(&bytes), &crc).unwrap(),
+            vec![(9, 9), (4, -6)]
+        );
+        assert_eq!(
+            dedupe_bytes(&mut bytes, &crc, Keep::Last).unwrap(),
+            Some((4, -6))
+        );
+        assert_eq!(remove_grab_bytes(&mut bytes, &crc).unwrap(), 1);
+        assert_eq!(read_grab_from(&mut bytes.as_slice(), &crc).unwrap(), None);
+    }
+
+    #[test]
+    fn crop_in_memory() {
+        let crc = Crc32::new();
+        let mut image = image::RgbaImage::new(5, 5);
+        image.put_pixel(1, 2, image::Rgba([255, 255, 255, 255]));
+        let mut bytes = vec![];
+        image
+            .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)
+            .unwrap();
+        let change = crop_bytes(&mut bytes, &crc, false).unwrap();
+        assert_eq!(change.bounds, (1, 2, 1, 1));
+        assert_eq!(
+            read_grab_from(&mut bytes.as_slice(), &crc).unwrap(),
+            Some((-1, -2))
+        );
+        let cropped = bytes.clone();
+        assert!(!crop_bytes(&mut bytes, &crc, false).unwrap().is_changed());
+        assert_eq!(bytes, cropped);
+    }
+
+    #[test]
+    fn errors_without_path() {
+        let mut bytes = b"not a png".to_vec();
+        let e = set_grab_bytes(&mut bytes, &Crc32::new(), 0, 0).unwrap_err();
+        assert!(e.path().is_none());
+        assert_eq!(bytes, b"not a png");
+    }
+}
+
+#[cfg(test)]
+mod journal_tests {
+    use crate::crc::*;
+    use crate::crop::*;
+    use crate::grab::*;
+    use crate::journal::*;
+    use crate::save::*;
+    use crate::temp_dir::TempDir;
+    use std::fs;
+    use std::sync::Arc;
+
+    #[test]
+    fn undo_last_run() {
+        let crc = Crc32::new();
+        let root = TempDir::new("journal_tests_undo_last_run");
+        let (first, second) = (root.png("first.png"), root.png("second.png"));
+        grab(&first, &crc, 1, 2, &SaveOptions::default()).unwrap();
+        let original = fs::read(&first).unwrap();
+
+        let options = SaveOptions {
+            journal: Some(Arc::new(Journal::new(&root))),
+            ..Default::default()
+        };
+        grab(&first, &crc, 5, 6, &options).unwrap();
+        nudge(&first, &crc, 1, 1, &options).unwrap();
+        push_grab(&second, &crc, 3, 4, &options).unwrap();
+        crop(&second, &crc, true, &options).unwrap();
+        let run = fs::read_dir(root.join(journal_directory))
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let manifest = fs::read_to_string(run.join("manifest")).unwrap();
+        assert!(manifest.starts_with("grab\t1\t2\t"));
+
+        let restored = undo(&root, &crc, &SaveOptions::default()).unwrap();
+        assert_eq!(restored.len(), 2);
+        assert_eq!(fs::read(&first).unwrap(), original);
+        assert_eq!(fs::read(&second).unwrap(), crate::png_tests::sample());
+        assert!(!run.exists());
+        assert!(
+            undo(&root, &crc, &SaveOptions::default())
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
+
+#[cfg(test)]
+mod record_tests {
+    use crate::batch::*;
+    use crate::crc::*;
+    use crate::grab::*;
+    use crate::record::*;
+    use crate::save::*;
+    use crate::temp_dir::TempDir;
+    use std::path::PathBuf;
+
+    fn records() -> Vec<Record> {
+        vec![
+            Record {
+                path: PathBuf::from("sprites/a,\"b\".png"),
+                width: 4,
+                height: 8,
+                offset: Some((-2, 3)),
+            },
+            Record {
+                path: PathBuf::from("c\td.png"),
+                width: 1,
+                height: 1,
+                offset: None,
+            },
+        ]
+    }
+
+    fn written(format: Format) -> String {
+        let mut bytes = vec![];
+        write_records(&mut bytes, &records(), format).unwrap();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(
+            written(Format::Json),
+            concat!(
+                "[\n",
+                "  {\"path\": \"sprites/a,\\\"b\\\".png\", \"x\": -2, \"y\": 3, \"width\": 4, \"height\": 8, \"has_grab\": true},\n",
+                "  {\"path\": \"c\\td.png\", \"x\": null, \"y\": null, \"width\": 1, \"height\": 1, \"has_grab\": false}\n",
+                "]\n"
+            )
+        );
+    }
+
+    #[test]
+    fn csv_and_tsv() {
+        assert_eq!(
+            written(Format::Csv),
+            "path,x,y,width,height,has_grab\n\"sprites/a,\"\"b\"\".png\",-2,3,4,8,true\nc\td.png,,,1,1,false\n"
+        );
+        assert_eq!(
+            written(Format::Tsv),
+            "path\tx\ty\twidth\theight\thas_grab\nsprites/a,\"b\".png\t-2\t3\t4\t8\ttrue\nc\\td.png\t\t\t1\t1\tfalse\n"
+        );
+    }
+
+    ///The path, offset, and line of a row
+    type Expected<'a> = (&'a str, Option<(&'a str, &'a str)>, usize);
+
+    fn rows(rows: &[Expected]) -> Vec<Row> {
+        rows.iter()
+            .map(|(path, offset, line)| Row {
+                path: PathBuf::from(path),
+                offset: offset.map(|(x, y)| (x.to_string(), y.to_string())),
+                line: *line,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parse_tables() {
+        let csv = "y,path,x\n-h,\"a,b.png\",w/2\n\n# no offset\n,c.png,\n";
+        assert_eq!(
+            parse_rows(csv, Format::Csv).unwrap(),
+            rows(&[("a,b.png", Some(("w/2", "-h")), 2), ("c.png", None, 5)])
+        );
+        let headerless = "\"a,b.png\",w/2,-h\nc.png,,";
+        assert_eq!(
+            parse_rows(headerless, Format::Csv).unwrap(),
+            rows(&[("a,b.png", Some(("w/2", "-h")), 1), ("c.png", None, 2)])
+        );
+        let tsv = "path\tx\ty\na,b.png\tw/2\t-h\nc.png\t\t\n";
+        assert_eq!(
+            parse_rows(tsv, Format::Tsv).unwrap(),
+            rows(&[("a,b.png", Some(("w/2", "-h")), 2), ("c.png", None, 3)])
+        );
+        let written = written(Format::Tsv);
+        assert_eq!(
+            parse_rows(&written, Format::Tsv).unwrap(),
+            rows(&[
+                ("sprites/a,\"b\".png", Some(("-2", "3")), 2),
+                ("c\td.png", None, 3)
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_json() {
+        let json = "[\n  {\"path\": \"a.png\", \"x\": -2, \"y\": \"h / 2\"},\n  {\"path\": \"b\\u0021.png\", \"x\": null, \"y\": null}\n]";
+        assert_eq!(
+            parse_rows(json, Format::Json).unwrap(),
+            rows(&[("a.png", Some(("-2", "h / 2")), 2), ("b!.png", None, 3)])
+        );
+        assert_eq!(
+            parse_rows(&written(Format::Json), Format::Json).unwrap(),
+            rows(&[
+                ("sprites/a,\"b\".png", Some(("-2", "3")), 2),
+                ("c\td.png", None, 3)
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        let line = |text, format| match parse_rows(text, format) {
+            Err(crate::Error::Parse { line, .. }) => line,
+            result => panic!("expected a parse error, found {result:?}"),
+        };
+        assert_eq!(line("path,x,y\na.png,1,2\nb.png,1", Format::Csv), 3);
+        assert_eq!(line("path,x\na.png,1", Format::Csv), 1);
+        assert_eq!(line("\"a.png,1,2", Format::Csv), 1);
+        assert_eq!(line("a.png,1,2\nb,c.png,1,2", Format::Csv), 2);
+        assert_eq!(line("path\tx\ty\na.png\t1\t2\t3", Format::Tsv), 2);
+        assert_eq!(line("a.png,1,1\nb.png,2,2\n./a.png,9,9", Format::Csv), 3);
+        assert_eq!(line("a.png,1,1\nb.png", Format::Csv), 2);
+        assert_eq!(line("path\tx\ty\na.png", Format::Tsv), 2);
+        assert_eq!(line("[\n{\"path\": \"a.png\", \"x\": 1}]", Format::Json), 2);
+        assert!(parse_rows("a.png,1,1\nb.png,2,2\na.png,1,1", Format::Csv).is_ok());
+        assert_eq!(
+            line(
+                "[\n{\"path\": \"a.png\", \"x\": true, \"y\": 1}]",
+                Format::Json
+            ),
+            2
+        );
+        assert_eq!(line("[\n{\"path\": \"a.png\"}\n", Format::Json), 3);
+    }
+
+    #[test]
+    fn json_limits() {
+        let mut json = String::from("[\n");
+        for i in 0..20000 {
+            json.push_str(&format!("{{\"path\": \"{i}.png\", \"x\": 1, \"y\": 2}},\n"));
+        }
+        json.push_str("{\"path\": \"last.png\", \"x\": 1}\n]");
+        match parse_rows(&json, Format::Json) {
+            Err(crate::Error::Parse { line, .. }) => assert_eq!(line, 20002),
+            result => panic!("expected a parse error, found {result:?}"),
+        }
+
+        let nested = format!("{}{}", "[".repeat(100000), "]".repeat(100000));
+        assert!(matches!(
+            parse_rows(&nested, Format::Json),
+            Err(crate::Error::Parse { line: 1, .. })
+        ));
+
+        let json = "[{\"path\": \"\\ud83d\\ude00.png\", \"x\": null, \"y\": null}]";
+        assert_eq!(
+            parse_rows(json, Format::Json).unwrap(),
+            rows(&[("\u{1f600}.png", None, 1)])
+        );
+        let json = "[{\"path\": \"\\ud83d.png\"}]";
+        assert!(parse_rows(json, Format::Json).is_err());
+    }
+
+    #[test]
+    fn apply_rows() {
+        let crc = Crc32::new();
+        let directory = TempDir::new("record_tests_apply_rows");
+        directory.png("a.png");
+        directory.png("b.png");
+        grab(
+            &directory.join("b.png"),
+            &crc,
+            1,
+            1,
+            &SaveOptions::default(),
+        )
+        .unwrap();
+        let manifest = directory.join("offsets.csv");
+        std::fs::write(&manifest, "path,x,y\na.png,w/2,-h\nb.png,,\n").unwrap();
+        let rows = read_rows(&manifest).unwrap();
+        let report = apply_all(
+            &rows,
+            &crc,
+            &BatchOptions::default(),
+            &SaveOptions::default(),
+            &mut (),
+        )
+        .unwrap();
+        assert_eq!(report.succeeded.len(), 2);
+        assert_eq!(
+            read_grab(&directory.join("a.png"), &crc).unwrap(),
+            Some((2, -4))
+        );
+        assert_eq!(read_grab(&directory.join("b.png"), &crc).unwrap(), None);
+
+        let other = directory.join("other.csv");
+        std::fs::write(&other, "path,x,y\nb.png,9,9\n").unwrap();
+        let rows = [rows, read_rows(&other).unwrap()].concat();
+        let result = apply_all(
+            &rows,
+            &crc,
+            &BatchOptions::default(),
+            &SaveOptions::default(),
+            &mut (),
+        );
+        match result {
+            Err(crate::Error::Parse { line, reason, .. }) => {
+                assert_eq!(line, 2);
+                assert!(reason.contains("line 3"));
+            }
+            result => panic!("expected a parse error, found {result:?}"),
+        }
+        assert_eq!(read_grab(&directory.join("b.png"), &crc).unwrap(), None);
+    }
+
+    #[test]
+    fn export_round_trip() {
+        let crc = Crc32::new();
+        let directory = TempDir::new("record_tests_export_round_trip");
+        let (a, b) = (
+            directory.png("sprites/a.png"),
+            directory.png("sprites/b.png"),
+        );
+        grab(&b, &crc, -5, 9, &SaveOptions::default()).unwrap();
+        let records = vec![
+            Record::read(&b, &crc).unwrap(),
+            Record::read(&a, &crc).unwrap(),
+        ];
+        let manifest = directory.join("snapshot").join("offsets.csv");
+        std::fs::create_dir_all(manifest.parent().unwrap()).unwrap();
+        export(&manifest, records, Format::Csv, &SaveOptions::default()).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&manifest).unwrap(),
+            "path,x,y,width,height,has_grab\n../sprites/a.png,,,4,4,false\n../sprites/b.png,-5,9,4,4,true\n"
+        );
+        let rows = read_rows(&manifest).unwrap();
+        assert_eq!(rows[1].offset, Some(("-5".into(), "9".into())));
+        assert_eq!(
+            std::fs::read(&rows[1].path).unwrap(),
+            std::fs::read(&b).unwrap()
+        );
+    }
+
+    #[test]
+    fn read_record() {
+        let crc = Crc32::new();
+        let (_directory, path) = TempDir::with_png("record_tests_read_record", "a.png");
+        grab(&path, &crc, 7, -1, &SaveOptions::default()).unwrap();
+        let record = Record::read(&path, &crc).unwrap();
+        assert_eq!((record.width, record.height), (4, 4));
+        assert_eq!(record.offset, Some((7, -1)));
+
+        push_grab(&path, &crc, 2, 3, &SaveOptions::default()).unwrap();
+        let (record, grabs) = Record::read_with_grabs(&path, &crc).unwrap();
+        assert_eq!(record.offset, Some((2, 3)));
+        assert_eq!(grabs, vec![(2, 3), (7, -1)]);
+    }
+}
+
+#[cfg(test)]
+mod glob_tests {
+    use crate::glob::*;
+    use std::path::Path;
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        Pattern::new(pattern, false).matches(Path::new(path))
+    }
+
+    #[test]
+    fn components() {
+        assert!(matches("a", "sprites/a/b.png"));
+        assert!(!matches("a", "sprites/alpha/b.png"));
+        assert!(matches("*.png", "sprites/b.png"));
+        assert!(!matches("*.png", "sprites/b.png.bak"));
+        assert!(matches("old/*.png", "sprites/old/b.png"));
+        assert!(!matches("old/*.png", "sprites/old/new/b.png"));
+        assert!(matches("old/**/*.png", "sprites/old/new/b.png"));
+        assert!(matches("old/**/b.png", "old/b.png"));
+        assert!(!matches("/old", "sprites/old/b.png"));
+        assert!(matches("/sprites/old", "./sprites/old/b.png"));
+    }
+
+    #[test]
+    fn specificity() {
+        let specificity = |pattern, path| Pattern::new(pattern, false).specificity(Path::new(path));
+        assert_eq!(specificity("**", "weapon/pickup/a.png"), Some((0, 0)));
+        assert_eq!(specificity("*.png", "weapon/pickup/a.png"), Some((0, 4)));
+        assert_eq!(
+            specificity("weapon/**", "weapon/pickup/a.png"),
+            Some((1, 6))
+        );
+        assert_eq!(specificity("pickup", "weapon/pickup/a.png"), Some((2, 6)));
+        assert_eq!(specificity("a", "a/b/a/c.png"), Some((3, 1)));
+        assert_eq!(specificity("/a", "a/b/a/c.png"), Some((1, 1)));
+        assert_eq!(specificity("pickup", "weapon/a.png"), None);
+    }
+
+    #[test]
+    fn matches_end() {
+        let pattern = Pattern::new("a", false);
+        assert!(pattern.matches_end(Path::new("b/a")));
+        assert!(!pattern.matches_end(Path::new("a/b")));
+        assert!(Pattern::new("a/**", false).matches_end(Path::new("a/b/c")));
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("b?.png", "b1.png"));
+        assert!(!matches("b?.png", "b.png"));
+        assert!(matches("[abc]*", "cat.png"));
+        assert!(matches("[!abc]*", "dog.png"));
+        assert!(!matches("[a-c]*", "dog.png"));
+        assert!(matches("[]x]", "]"));
+        assert!(matches("[x", "[x"));
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+        assert!(!matches("A.PNG", "a.png"));
+        assert!(Pattern::new("A.PNG", true).matches(Path::new("Sprites/a.png")));
+    }
+}
+
+#[cfg(test)]
+mod walk_tests {
+    use crate::glob::Pattern;
+    use crate::temp_dir::{TempDir, relative};
+    use crate::walk::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn list() {
+        assert_eq!(
+            parse_list(b"a.png\r\nsprites/b c.png\n\n"),
+            [PathBuf::from("a.png"), "sprites/b c.png".into()]
+        );
+        assert_eq!(
+            parse_list(b"a\nb.png\0c.png\0"),
+            [PathBuf::from("a\nb.png"), "c.png".into()]
+        );
+        assert!(parse_list(b"").is_empty());
+    }
+
+    #[test]
+    fn walk() {
+        let root = TempDir::new("walk_tests_walk");
+        for path in [
+            "b.png",
+            "a.PNG",
+            "notes.txt",
+            "old/c.png",
+            "new/d.png",
+            ".putpng/run/0.png",
+        ] {
+            root.write(path, b"");
+        }
+        let mut filter = Filter {
+            recursive: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            relative(&root, &filter.files([&*root]).unwrap()),
+            ["a.PNG", "b.png", "new/d.png", "old/c.png"]
+        );
+        filter.exclude = vec![Pattern::new("old", false)];
+        filter.include = vec![Pattern::new("*.png", true)];
+        assert_eq!(
+            relative(&root, &filter.files([&*root]).unwrap()),
+            ["a.PNG", "b.png", "new/d.png"]
+        );
+        filter.recursive = false;
+        filter.include.clear();
+        let file = root.join("old/c.png");
+        assert!(filter.files([&*root, &file]).unwrap() == [root.to_path_buf()]);
+
+        filter.exclude.clear();
+        let named = [
+            root.join("notes.txt"),
+            root.join("b.png"),
+            root.join("e.png"),
+        ];
+        assert_eq!(
+            filter.files(named.iter().map(|p| p.as_path())).unwrap(),
+            &named[1..]
+        );
+    }
+}
+
+#[cfg(test)]
+mod config_tests {
+    use crate::config::*;
+    use crate::crc::*;
+    use crate::error::Error;
+    use crate::grab::*;
+    use crate::png_tests::sample;
+    use std::io::Cursor;
+    use std::path::Path;
+
+    const project: &str = r#"
+# everything gets the same offset and is cropped
+[[rule]]
+glob = "weapon/**"
+x = 16
+y = 32
+crop = true
+
+[[rule]]
+glob = [
+    "pickup",
+    "projectile", # trailing comma
+]
+x = "w / 2"
+y = 'h / 2'
+
+[[rule]]
+glob = "pickup/ammo*.png"
+crop = false
+"#;
+
+    fn offset(settings: &Settings) -> Option<(&str, &str)> {
+        settings
+            .offset
+            .as_ref()
+            .map(|(x, y)| (x.as_str(), y.as_str()))
+    }
+
+    #[test]
+    fn most_specific_wins() {
+        let config = Config::parse(project, Path::new("project")).unwrap();
+        assert_eq!(config.rules.len(), 3);
+        assert_eq!(config.rules[1].line, 9);
+
+        let settings = config.settings(Path::new("project/weapon/fire.png"));
+        assert_eq!(offset(&settings), Some(("16", "32")));
+        assert_eq!(settings.crop, Some(true));
+
+        let settings = config.settings(Path::new("project/weapon/pickup/gun.png"));
+        assert_eq!(offset(&settings), Some(("w / 2", "h / 2")));
+        assert_eq!(settings.crop, Some(true));
+
+        let settings = config.settings(Path::new("project/pickup/ammo1.png"));
+        assert_eq!(offset(&settings), Some(("w / 2", "h / 2")));
+        assert_eq!(settings.crop, Some(false));
+        assert!(config.settings(Path::new("other/weapon/x.png")).is_empty());
+        assert!(
+            config
+                .settings(Path::new("project/../weapon/x.png"))
+                .is_empty()
+        );
+
+        let other = Config::parse("[[rule]]\nglob = 'other'\ncrop = true", Path::new("."));
+        assert!(other.unwrap().settings(Path::new("a.png")).is_empty());
+    }
+
+    #[test]
+    fn ties_go_to_the_last_rule() {
+        let text = "[[rule]]\nglob = '*.png'\nx = 1\ny = 1\n[[rule]]\nglob = 'a*png'\nx = 2\ny = 2";
+        let config = Config::parse(text, Path::new(".")).unwrap();
+        assert_eq!(
+            offset(&config.settings(Path::new("a.png"))),
+            Some(("2", "2"))
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        let line = |text: &str| match Config::parse(text, Path::new(".")) {
+            Err(Error::Parse { line, .. }) => line,
+            result => panic!("expected a parse error, found {result:?}"),
+        };
+        assert_eq!(line("[[rule]]\nglob = '*'\nx = 1"), 1);
+        assert_eq!(line("[[rule]]\nx = 1\ny = 1"), 1);
+        assert_eq!(line("[[rule]]\nglob = '*'\ncrop = 1"), 3);
+        assert_eq!(line("[[rule]]\nglob = '*'\nx = 1.5\ny = 1"), 3);
+        assert_eq!(line("[[rule]]\nglob = '*'\nsize = 1"), 3);
+        assert_eq!(line("[[rule]]\nglob = '*'\nglob = '*'"), 3);
+        assert_eq!(line("[rule]\nglob = '*'"), 1);
+        assert_eq!(line("\n[sprites]"), 2);
+        assert_eq!(line("[[rule]]\nglob = \"*"), 2);
+        assert_eq!(line("[[rule]]\nglob = '*' crop = true"), 2);
+        assert_eq!(line("[[rule]]\nglob.name = '*'"), 2);
+        assert_eq!(line("ignore_case = 'yes'"), 1);
+        assert_eq!(line(&format!("\n\nx = {}", "[".repeat(100000))), 3);
+        let rules = "[[rule]]\nglob = '*'\ncrop = true\n".repeat(5000);
+        assert_eq!(line(&format!("{rules}[[rule]]\nglob = 1")), 15002);
+    }
+
+    #[test]
+    fn ignore_case() {
+        let text = "ignore_case = true\n[[rule]]\nglob = 'PICKUP'\ncrop = true";
+        let config = Config::parse(text, Path::new(".")).unwrap();
+        assert_eq!(config.settings(Path::new("pickup/a.png")).crop, Some(true));
+    }
+
+    #[test]
+    fn run_in_memory() {
+        let crc = Crc32::new();
+        let mut bytes = sample();
+        let settings = Settings {
+            offset: Some(("w / 2".into(), "h".into())),
+            ..Default::default()
+        };
+        let change = run_bytes(&mut bytes, &crc, &settings).unwrap();
+        assert!(change.is_changed());
+        assert_eq!(change.offset(), Some((2, 4)));
+        assert_eq!(
+            read_grab_from(&mut bytes.as_slice(), &crc).unwrap(),
+            Some((2, 4))
+        );
+        let ran = bytes.clone();
+        assert!(!run_bytes(&mut bytes, &crc, &settings).unwrap().is_changed());
+        assert_eq!(bytes, ran);
+    }
+
+    #[test]
+    fn run_twice_with_crop() {
+        let crc = Crc32::new();
+        let mut image = image::RgbaImage::new(8, 6);
+        image.put_pixel(2, 1, image::Rgba([255, 0, 0, 255]));
+        image.put_pixel(4, 3, image::Rgba([0, 255, 0, 255]));
+        let mut bytes = vec![];
+        image
+            .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)
+            .unwrap();
+        set_grab_bytes(&mut bytes, &crc, 9, 9).unwrap();
+        let settings = Settings {
+            offset: Some(("16".into(), "h * 2".into())),
+            crop: Some(true),
+            ..Default::default()
+        };
+        let change = run_bytes(&mut bytes, &crc, &settings).unwrap();
+        assert!(change.is_changed());
+        assert_eq!(change.crop.unwrap().bounds, (2, 1, 3, 3));
+        assert_eq!(change.offset(), Some((16, 6)));
+        let ran = bytes.clone();
+        let change = run_bytes(&mut bytes, &crc, &settings).unwrap();
+        assert!(!change.is_changed());
+        assert_eq!(change.offset(), Some((16, 6)));
+        assert_eq!(bytes, ran);
+    }
+}
+
+#[cfg(test)]
+mod ignore_tests {
+    use crate::ignore::*;
+    use crate::temp_dir::{TempDir, relative};
+    use crate::walk::*;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn rules() {
+        let file = IgnoreFile::parse(
+            "# comment\n\n*.psd.png\nbrightmaps/\nsrc/ref\n!keep.psd.png\n\\#hash.png\ntrailing.png  \n",
+        );
+        let decide = |path: &str, is_dir| file.decide(Path::new(path), is_dir);
+        assert_eq!(decide("a/b.psd.png", false), Some(true));
+        assert_eq!(decide("a/keep.psd.png", false), Some(false));
+        assert_eq!(decide("a/brightmaps", true), Some(true));
+        assert_eq!(decide("a/brightmaps", false), None);
+        assert_eq!(decide("src/ref", true), Some(true));
+        assert_eq!(decide("a/src/ref", true), None);
+        assert_eq!(decide("src/ref/a.png", false), None);
+        assert_eq!(decide("#hash.png", false), Some(true));
+        assert_eq!(decide("trailing.png", false), Some(true));
+        assert_eq!(decide("comment", false), None);
+    }
+
+    #[test]
+    fn walk() {
+        let root = TempDir::new("ignore_tests_walk");
+        for path in [
+            "a.png",
+            "ref.png",
+            "psd/b.png",
+            "sprites/c.png",
+            "sprites/ref.png",
+            "sprites/old/d.png",
+        ] {
+            root.write(path, b"");
+        }
+        fs::write(root.join(ignore_name), "ref.png\npsd/\n!psd/b.png\n").unwrap();
+        fs::write(root.join("sprites").join(ignore_name), "!ref.png\nold\n").unwrap();
+
+        let mut filter = Filter {
+            recursive: true,
+            ignore_files: true,
+            ..Default::default()
+        };
+        let relative = |filter: &Filter| relative(&root, &filter.files([&*root]).unwrap());
+        assert_eq!(
+            relative(&filter),
+            ["a.png", "sprites/c.png", "sprites/ref.png"]
+        );
+        let explicit = [root.join("psd/b.png"), root.join("sprites/../ref.png")];
+        assert!(
+            filter
+                .files(explicit.iter().map(|p| p.as_path()))
+                .unwrap()
+                .is_empty()
+        );
+        filter.ignore_files = false;
+        assert_eq!(relative(&filter).len(), 6);
+    }
 }
@@ -1,8 +1,20 @@
 use clap::{Parser, Subcommand};
+use putpng::batch::*;
+use putpng::config::*;
 use putpng::crc::*;
 use putpng::crop::*;
+use putpng::glob::Pattern;
 use putpng::grab::*;
-use std::path::PathBuf;
+use putpng::journal::*;
+use putpng::png::Header;
+use putpng::record::*;
+use putpng::save::*;
+use putpng::verify::*;
+use putpng::walk::{Filter, parse_list};
+use std::collections::HashSet;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 #[derive(Parser)]
 #[command(
@@ -17,52 +29,579 @@ struct Args {
     #[arg(global = true)]
     paths: Vec<PathBuf>,
 
+    ///Also read paths from this file ('-' for stdin), one per line or separated by NUL characters (for apply these are more manifests)
+    #[arg(long, global = true, value_name = "FILE")]
+    files_from: Option<PathBuf>,
+
     ///If paths contain these strings, ignore them
     #[arg(short, long, global = true, num_args = 1..)]
     ignore: Vec<String>,
+
+    ///Go through the specified directories and everything below them for png files
+    #[arg(short = 'R', long, global = true)]
+    recursive: bool,
+
+    ///Only use paths that match one of these globs ('*' and '?' stay within a component, '**' spans any number of them)
+    #[arg(long, global = true, num_args = 1..)]
+    include: Vec<String>,
+
+    ///Leave out paths that match any of these globs (a glob without '/' matches any single component)
+    #[arg(long, global = true, num_args = 1..)]
+    exclude: Vec<String>,
+
+    ///Match the include and exclude globs regardless of case
+    #[arg(long, global = true)]
+    ignore_case: bool,
+
+    ///Don't leave out the paths ignored by .putpngignore files
+    #[arg(long, global = true)]
+    no_ignore_files: bool,
+
+    ///Keep the modification time of modified files
+    #[arg(long, global = true)]
+    keep_mtime: bool,
+
+    ///Back up every file before changing it so the run can be undone with the undo command
+    #[arg(long, global = true)]
+    backup: bool,
+
+    ///Show what would change without writing anything
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    ///Stop at the first file that fails instead of processing the rest (processes one file at a time)
+    #[arg(long, global = true)]
+    fail_fast: bool,
+
+    ///Print the path, offset, and size of each file as 'json', 'csv', or 'tsv' instead of messages
+    #[arg(long, global = true)]
+    format: Option<Format>,
+
+    ///How many files to process at once (0 uses every available core)
+    #[arg(short, long, global = true, default_value_t = 0)]
+    jobs: usize,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     ///Apply the given offsets to the specified png files
-    Grab { x: String, y: String },
+    Grab {
+        #[arg(allow_hyphen_values = true)]
+        x: String,
+        #[arg(allow_hyphen_values = true)]
+        y: String,
+        ///Add the offsets to the current offsets instead of replacing them
+        #[arg(short, long)]
+        relative: bool,
+    },
+    ///Add the given offsets to the current offsets of the specified png files
+    Nudge {
+        #[arg(allow_hyphen_values = true)]
+        x: String,
+        #[arg(allow_hyphen_values = true)]
+        y: String,
+    },
     ///Crop out the empty edges of the specified png files, but keep the relative offset
-    Crop,
+    Crop {
+        ///Remove every ancillary chunk other than the grab offset and transparency
+        #[arg(short, long)]
+        strip: bool,
+    },
+    ///Removes the grab offsets from the specified png files
+    Ungrab,
+    ///Collapses duplicate grab offsets of the specified png files into one
+    Dedupe {
+        ///Which offset to keep: 'first', 'last', or its position starting at 1
+        #[arg(short, long, default_value = "first")]
+        keep: Keep,
+    },
     ///Displays the grab offsets of the specified png files
-    Show,
+    Show {
+        ///Also display the size, bit depth, and color type read from the header
+        #[arg(long)]
+        header: bool,
+    },
+    ///Checks the chunks of the specified png files and reports any problems
+    Verify,
+    ///Writes the path, offset, and size of the specified png files sorted by path as csv, tsv, or json
+    Export {
+        ///The file to write to (its extension picks the format unless --format is given), otherwise stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    ///Sets the offsets listed in the specified csv, tsv, or json files (rows of path, x, and y where x and y may use w and h)
+    Apply,
+    ///Restores the files changed by the last run made with --backup in the current directory
+    Undo,
+    ///Applies the rules of a putpng.toml config to the png files below its directory (or the specified paths)
+    Run {
+        ///The config file to use instead of the putpng.toml found in the current directory or its parents
+        #[arg(short, long)]
+        config: Option<PathBuf>,
+    },
 }
 
-fn ignoring(ignore: Vec<String>) -> impl Fn(&&PathBuf) -> bool {
-    move |p| !ignore.iter().any(|i| p.to_string_lossy().contains(i))
+fn is_ignored(ignore: &[String], path: &Path) -> bool {
+    ignore.iter().any(|i| path.to_string_lossy().contains(i))
+}
+
+//Goes to stderr so that it is seen even when the output is csv, tsv, or json
+fn warn_duplicates(path: &Path, offsets: &[(i32, i32)]) {
+    if offsets.len() > 1 {
+        eprintln!(
+            "warning: {path:?} has {} grAb chunks: {offsets:?}",
+            offsets.len()
+        );
+    }
+}
+
+fn offset(offset: Option<(i32, i32)>) -> String {
+    offset.map_or("none".into(), |(x, y)| format!("({x}, {y})"))
+}
+
+//Splits what `run` did into the grab and crop outcomes that changed the file
+fn changed_parts(change: RunChange) -> impl Iterator<Item = Outcome> {
+    [
+        change.crop.map(Outcome::Cropped),
+        change.grab.map(Outcome::Grabbed),
+    ]
+    .into_iter()
+    .flatten()
+    .filter(Outcome::is_changed)
+}
+
+///Prints what happened to each file, or what would have happened for a dry run
+struct Printer<'a> {
+    dry_run: bool,
+    crc: &'a Crc32,
+    ///Collects the records to print at the end instead of printing messages
+    format: Option<Format>,
+    records: Vec<Record>,
+}
+
+impl Printer<'_> {
+    //Uses the outcome rather than rereading the file so that dry runs show the new offsets too
+    fn record(&self, path: &Path, outcome: &Outcome) -> Result<Record, putpng::Error> {
+        let offset = match *outcome {
+            Outcome::Grabbed(change) | Outcome::Nudged(change) => Some(change.after),
+            Outcome::Pushed(offset) => Some(offset),
+            Outcome::Cropped(change) if change.is_changed() => Some(change.grab.after),
+            Outcome::Cropped(change) => change.grab.before,
+            Outcome::Ungrabbed(_) => None,
+            Outcome::Deduped(_) => return Record::read(path, self.crc),
+            Outcome::Ran(change) => change.offset(),
+        };
+        let (width, height) = match *outcome {
+            Outcome::Cropped(change)
+            | Outcome::Ran(RunChange {
+                crop: Some(change), ..
+            }) => (change.bounds.2, change.bounds.3),
+            _ => {
+                let header = Header::open(path, self.crc)?;
+                (header.width, header.height)
+            }
+        };
+        Ok(Record {
+            path: path.to_path_buf(),
+            width,
+            height,
+            offset,
+        })
+    }
+
+    fn print_records(&mut self) {
+        if let Some(format) = self.format {
+            let records = std::mem::take(&mut self.records);
+            write_records(&mut std::io::stdout().lock(), &records, format)
+                .expect("failed to write to stdout");
+        }
+    }
+
+    ///Prints the records and the summary of the batch, then exits if anything failed
+    fn finish(&mut self, result: Result<BatchReport, putpng::Error>) {
+        self.print_records();
+        match result {
+            Ok(report) => {
+                let summary = match self.dry_run {
+                    true => format!("{report} (dry run, nothing was written)"),
+                    false => report.to_string(),
+                };
+                //Keeps the structured output free of anything else
+                match self.format {
+                    Some(_) => eprintln!("{summary}"),
+                    None => println!("{summary}"),
+                }
+                if !report.is_success() {
+                    std::process::exit(1);
+                }
+            }
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    fn preview(&self, path: &Path, outcome: &Outcome) {
+        match *outcome {
+            Outcome::Grabbed(change) => println!(
+                "would grab {path:?}: {} -> {}",
+                offset(change.before),
+                offset(Some(change.after))
+            ),
+            Outcome::Nudged(change) => println!(
+                "would nudge {path:?}: {} -> {}",
+                offset(change.before),
+                offset(Some(change.after))
+            ),
+            Outcome::Pushed((x, y)) => println!("would add ({x}, {y}) to {path:?}"),
+            Outcome::Cropped(CropChange {
+                size: (w, h),
+                bounds: (left, top, width, height),
+                grab,
+                stripped,
+            }) => println!(
+                "would crop {path:?}: {w}x{h} -> {width}x{height} at ({left}, {top}), offset {} -> {}{}",
+                offset(grab.before),
+                offset(Some(grab.after)),
+                if stripped {
+                    ", stripping ancillary chunks"
+                } else {
+                    ""
+                }
+            ),
+            Outcome::Ungrabbed(count) => {
+                println!("would remove {count} grAb chunk(s) from {path:?}")
+            }
+            Outcome::Deduped(kept) => println!("would dedupe {path:?} to {}", offset(kept)),
+            Outcome::Ran(change) => {
+                for outcome in changed_parts(change) {
+                    self.preview(path, &outcome);
+                }
+            }
+        }
+    }
+}
+
+impl Reporter for Printer<'_> {
+    fn processed(&mut self, path: &Path, outcome: &Outcome) {
+        if self.format.is_some() {
+            match self.record(path, outcome) {
+                Ok(record) => self.records.push(record),
+                Err(e) => eprintln!("{e}"),
+            }
+            return;
+        }
+        if self.dry_run && outcome.is_changed() {
+            return self.preview(path, outcome);
+        }
+        match *outcome {
+            Outcome::Grabbed(change) | Outcome::Nudged(change) if !change.is_changed() => {
+                let (x, y) = change.after;
+                println!("{path:?} is unchanged at ({x}, {y})");
+            }
+            Outcome::Grabbed(GrabChange { after: (x, y), .. }) | Outcome::Pushed((x, y)) => {
+                println!("grabbed {path:?} successfully at ({x}, {y})!")
+            }
+            Outcome::Nudged(GrabChange { after: (x, y), .. }) => {
+                println!("nudged {path:?} successfully to ({x}, {y})!")
+            }
+            Outcome::Cropped(change) if change.is_changed() => {
+                println!("Cropped {path:?} successfully!")
+            }
+            Outcome::Cropped(_) => println!("{path:?} is unchanged"),
+            Outcome::Ungrabbed(1) => println!("removed 1 grAb chunk from {path:?}"),
+            Outcome::Ungrabbed(count) => println!("removed {count} grAb chunks from {path:?}"),
+            Outcome::Deduped(Some((x, y))) => {
+                println!("deduped {path:?} successfully at ({x}, {y})!")
+            }
+            Outcome::Deduped(None) => println!("{path:?} does not have duplicate grAb chunks"),
+            Outcome::Ran(change) if !change.is_changed() => println!("{path:?} is unchanged"),
+            Outcome::Ran(change) => {
+                for outcome in changed_parts(change) {
+                    self.processed(path, &outcome);
+                }
+            }
+        }
+    }
+
+    fn failed(&mut self, error: &putpng::Error) {
+        eprintln!("{error}");
+    }
 }
 
 fn main() {
-    let args = Args::parse_from(wild::args());
+    let mut args = Args::parse_from(wild::args());
+    if let Some(list) = &args.files_from {
+        let bytes = match list == Path::new("-") {
+            true => {
+                let mut bytes = vec![];
+                std::io::stdin().read_to_end(&mut bytes).map(|_| bytes)
+            }
+            false => std::fs::read(list),
+        };
+        match bytes {
+            Ok(bytes) => args.paths.extend(parse_list(&bytes)),
+            Err(e) => {
+                eprintln!("{list:?}: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
 
-    let (commands, paths) = (
-        args.commands,
-        args.paths
+    let options = SaveOptions {
+        keep_modified: args.keep_mtime,
+        dry_run: args.dry_run,
+        journal: args.backup.then(|| Arc::new(Journal::new(Path::new(".")))),
+    };
+    let crc = Crc32::new();
+    let mut printer = Printer {
+        dry_run: args.dry_run,
+        crc: &crc,
+        format: args.format,
+        records: vec![],
+    };
+    let batch = BatchOptions {
+        fail_fast: args.fail_fast,
+        jobs: args.jobs,
+    };
+    let patterns = |globs: &[String]| {
+        globs
             .iter()
-            .filter(ignoring(args.ignore))
-            .map(PathBuf::as_path),
-    );
+            .map(|glob| Pattern::new(glob, args.ignore_case))
+            .collect()
+    };
+    let filter = Filter {
+        recursive: args.recursive,
+        include: patterns(&args.include),
+        exclude: patterns(&args.exclude),
+        ignore_files: !args.no_ignore_files,
+    };
+    let (commands, ignore) = (args.commands, args.ignore);
+    let config = match &commands {
+        Commands::Run { config } => {
+            let Some(path) = config.clone().or_else(|| Config::find(Path::new("."))) else {
+                eprintln!("{config_name} was not found in the current directory or its parents");
+                std::process::exit(1);
+            };
+            match Config::read(&path) {
+                Ok(config) => Some(config),
+                Err(e) => {
+                    eprintln!("{e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        _ => None,
+    };
+    let exit = |e: putpng::Error| -> Vec<PathBuf> {
+        eprintln!("{e}");
+        std::process::exit(1);
+    };
+    let files = match (&commands, &config) {
+        //The paths of apply are the manifests rather than the pngs
+        (Commands::Apply, _) => vec![],
+        //A run goes through the whole project unless it is given paths
+        (Commands::Run { .. }, Some(config)) => {
+            let filter = Filter {
+                recursive: true,
+                ..filter.clone()
+            };
+            match args.paths.is_empty() {
+                true => filter.files([config.directory.as_path()]),
+                false => filter.files(args.paths.iter().map(PathBuf::as_path)),
+            }
+            .unwrap_or_else(exit)
+        }
+        _ => filter
+            .files(args.paths.iter().map(PathBuf::as_path))
+            .unwrap_or_else(exit),
+    };
+    let paths = files
+        .iter()
+        .map(PathBuf::as_path)
+        .filter(|p| !is_ignored(&ignore, p));
     match commands {
-        Commands::Grab { x, y } => {
-            let crc = Crc32::new();
-            let _ = grab_all(paths, &crc, &x, &y, false).inspect_err(|e| eprintln!("{e}"));
+        Commands::Grab { x, y, relative } => {
+            let mode = match relative {
+                true => GrabMode::Nudge,
+                false => GrabMode::Set,
+            };
+            let result = grab_all(paths, &crc, &x, &y, mode, &batch, &options, &mut printer);
+            printer.finish(result);
+        }
+        Commands::Nudge { x, y } => {
+            let result = grab_all(
+                paths,
+                &crc,
+                &x,
+                &y,
+                GrabMode::Nudge,
+                &batch,
+                &options,
+                &mut printer,
+            );
+            printer.finish(result);
+        }
+        Commands::Crop { strip } => {
+            let result = crop_all(paths, &crc, strip, &batch, &options, &mut printer);
+            printer.finish(result);
+        }
+        Commands::Ungrab => {
+            let result = remove_grab_all(paths, &crc, &batch, &options, &mut printer);
+            printer.finish(result);
         }
-        Commands::Crop => {
-            let crc = Crc32::new();
-            let _ = crop_all(paths, &crc).inspect_err(|e| eprintln!("{e}"));
+        Commands::Dedupe { keep } => {
+            let result = dedupe_all(paths, &crc, keep, &batch, &options, &mut printer);
+            printer.finish(result);
         }
-        Commands::Show => {
+        Commands::Show { header } => {
+            let mut failed = false;
             for path in paths {
-                match read_grab(path) {
-                    Ok(Some(offset)) => println!("{path:?}: {offset:?}"),
-                    Err(e) => eprintln!("{e}"),
-                    _ => println!("{path:?} does not have an offset"),
+                if printer.format.is_some() {
+                    match Record::read_with_grabs(path, &crc) {
+                        Ok((record, offsets)) => {
+                            warn_duplicates(path, &offsets);
+                            printer.records.push(record);
+                        }
+                        Err(e) => {
+                            failed = true;
+                            eprintln!("{e}");
+                        }
+                    }
+                    continue;
                 }
+                if header {
+                    match Header::open(path, &crc) {
+                        Ok(h) => println!(
+                            "{path:?} is {}x{} with {}-bit {}",
+                            h.width,
+                            h.height,
+                            h.bit_depth,
+                            h.color_name()
+                        ),
+                        Err(e) => {
+                            failed = true;
+                            eprintln!("{e}");
+                            continue;
+                        }
+                    }
+                }
+                match read_grabs(path, &crc) {
+                    Ok(offsets) if offsets.is_empty() => {
+                        println!("{path:?} does not have an offset")
+                    }
+                    Ok(offsets) => {
+                        println!("{path:?}: {:?}", offsets[0]);
+                        warn_duplicates(path, &offsets);
+                    }
+                    Err(e) => {
+                        failed = true;
+                        eprintln!("{e}");
+                    }
+                }
+            }
+            printer.print_records();
+            if failed {
+                std::process::exit(1);
+            }
+        }
+        Commands::Verify => {
+            let mut failed = false;
+            for path in paths {
+                match verify(path, &crc) {
+                    Ok(problems) if problems.is_empty() => println!("{path:?} is valid"),
+                    Ok(problems) => {
+                        failed = true;
+                        for problem in problems {
+                            eprintln!("{path:?}: {problem}");
+                        }
+                    }
+                    Err(e) => {
+                        failed = true;
+                        eprintln!("{e}");
+                    }
+                }
+            }
+            if failed {
+                std::process::exit(1);
             }
         }
+        Commands::Export { output } => {
+            let mut records = vec![];
+            let mut failed = false;
+            for path in paths {
+                match Record::read(path, &crc) {
+                    Ok(record) => records.push(record),
+                    Err(e) if batch.fail_fast => {
+                        eprintln!("{e}");
+                        std::process::exit(1);
+                    }
+                    Err(e) => {
+                        failed = true;
+                        eprintln!("{e}");
+                    }
+                }
+            }
+            let result = match &output {
+                Some(output) => {
+                    let format = printer.format.unwrap_or(Format::from_extension(output));
+                    export(output, records, format, &options)
+                }
+                None => {
+                    records.sort_by(|a, b| a.path.cmp(&b.path));
+                    let format = printer.format.unwrap_or(Format::Csv);
+                    write_records(&mut std::io::stdout().lock(), &records, format)
+                        .map_err(Into::into)
+                }
+            };
+            if let Err(e) = result {
+                eprintln!("{e}");
+                failed = true;
+            }
+            if failed {
+                std::process::exit(1);
+            }
+        }
+        Commands::Apply => {
+            let mut rows = vec![];
+            for manifest in &args.paths {
+                match read_rows(manifest) {
+                    Ok(read) => rows.extend(read),
+                    Err(e) => {
+                        eprintln!("{e}");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            let kept: HashSet<PathBuf> = filter
+                .files(rows.iter().map(|row| row.path.as_path()))
+                .unwrap_or_else(exit)
+                .into_iter()
+                .collect();
+            rows.retain(|row| kept.contains(&row.path) && !is_ignored(&ignore, &row.path));
+            let result = apply_all(&rows, &crc, &batch, &options, &mut printer);
+            printer.finish(result);
+        }
+        Commands::Run { .. } => {
+            let config = config.expect("the config was read before the files");
+            let result = run_all(paths, &crc, &config, &batch, &options, &mut printer);
+            printer.finish(result);
+        }
+        Commands::Undo => match undo(Path::new("."), &crc, &options) {
+            Ok(restored) if restored.is_empty() => println!("there is nothing to undo"),
+            Ok(restored) => {
+                for path in restored {
+                    match options.dry_run {
+                        true => println!("would restore {path:?}"),
+                        false => println!("restored {path:?}"),
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        },
     }
 }
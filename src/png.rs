@@ -0,0 +1,216 @@
+use crate::crc::*;
+use crate::error::Result;
+use crate::save::*;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::path::Path;
+
+pub const signature: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+///A single chunk of a png with its four character kind, its data, and its stored crc
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chunk {
+    pub kind: [u8; 4],
+    pub data: Vec<u8>,
+    pub crc: u32,
+}
+
+impl Chunk {
+    ///Creates a chunk and calculates its crc
+    pub fn new(crc: &Crc32, kind: &[u8; 4], data: Vec<u8>) -> Self {
+        Self {
+            kind: *kind,
+            crc: crc.calculate(&[kind, data.as_slice()].concat()),
+            data,
+        }
+    }
+
+    pub fn is(&self, kind: &[u8; 4]) -> bool {
+        &self.kind == kind
+    }
+
+    ///Whether a decoder may safely ignore this chunk (the first letter of its kind is lowercase)
+    pub fn is_ancillary(&self) -> bool {
+        self.kind[0].is_ascii_lowercase()
+    }
+
+    pub(crate) fn read(reader: &mut impl Read) -> Result<Self> {
+        let mut buffer = <[u8; 4]>::default();
+        read_exact(reader, &mut buffer)?;
+        let length = u32::from_be_bytes(buffer);
+        let mut kind = <[u8; 4]>::default();
+        read_exact(reader, &mut kind)?;
+        let mut data = vec![];
+        reader.take(length as u64).read_to_end(&mut data)?;
+        if data.len() != length as usize {
+            return Err(Error::Truncated.into());
+        }
+        read_exact(reader, &mut buffer)?;
+        Ok(Self {
+            kind,
+            data,
+            crc: u32::from_be_bytes(buffer),
+        })
+    }
+
+    fn write(&self, writer: &mut impl Write) -> Result<()> {
+        writer.write_all(&(self.data.len() as u32).to_be_bytes())?;
+        writer.write_all(&self.kind)?;
+        writer.write_all(&self.data)?;
+        writer.write_all(&self.crc.to_be_bytes())?;
+        Ok(())
+    }
+}
+
+//Checks the signature and reads the IHDR chunk that must follow it
+fn read_header(reader: &mut impl Read, crc: &Crc32) -> Result<Chunk> {
+    let mut buffer = <[u8; 8]>::default();
+    match reader.read_exact(&mut buffer) {
+        Ok(()) if buffer == signature => (),
+        Ok(()) => return Err(Error::NotPng.into()),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Err(Error::NotPng.into()),
+        Err(e) => return Err(e.into()),
+    }
+    let header = Chunk::read(reader)?;
+    if !header.is(b"IHDR") {
+        return Err(Error::MissingHeader.into());
+    }
+    if header.data.len() != 13 {
+        return Err(Error::HeaderLength(header.data.len()).into());
+    }
+    if Chunk::new(crc, b"IHDR", header.data.clone()).crc != header.crc {
+        return Err(Error::HeaderCrc.into());
+    }
+    Ok(header)
+}
+
+///The fields of the IHDR chunk
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Header {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: u8,
+    pub interlaced: bool,
+}
+
+impl Header {
+    fn parse(chunk: &Chunk) -> Self {
+        let data = &chunk.data;
+        Self {
+            width: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+            height: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            bit_depth: data[8],
+            color_type: data[9],
+            interlaced: data[12] != 0,
+        }
+    }
+
+    ///Reads only the signature and the IHDR chunk without going through the rest of the png
+    pub fn read(reader: &mut impl Read, crc: &Crc32) -> Result<Self> {
+        read_header(reader, crc).map(|chunk| Self::parse(&chunk))
+    }
+
+    pub fn open(path: &Path, crc: &Crc32) -> Result<Self> {
+        File::open(path)
+            .map_err(Into::into)
+            .and_then(|file| Self::read(&mut BufReader::new(file), crc))
+            .map_err(|e| e.at(path))
+    }
+
+    ///The name of the color type as used by the png specification
+    pub fn color_name(&self) -> &'static str {
+        match self.color_type {
+            0 => "grayscale",
+            2 => "truecolor",
+            3 => "indexed",
+            4 => "grayscale with alpha",
+            6 => "truecolor with alpha",
+            _ => "unknown",
+        }
+    }
+}
+
+///Every chunk of a png up to and including IEND
+#[derive(Clone, Debug, PartialEq)]
+pub struct PngFile {
+    pub chunks: Vec<Chunk>,
+}
+
+impl PngFile {
+    ///Reads every chunk after checking the signature and the IHDR chunk
+    pub fn read(reader: &mut impl Read, crc: &Crc32) -> Result<Self> {
+        let mut chunks = vec![read_header(reader, crc)?];
+        loop {
+            let chunk = Chunk::read(reader)?;
+            let is_end = chunk.is(b"IEND");
+            chunks.push(chunk);
+            if is_end {
+                return Ok(Self { chunks });
+            }
+        }
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> Result<()> {
+        writer.write_all(&signature)?;
+        for chunk in &self.chunks {
+            chunk.write(writer)?;
+        }
+        Ok(())
+    }
+
+    pub fn open(path: &Path, crc: &Crc32) -> Result<Self> {
+        File::open(path)
+            .map_err(Into::into)
+            .and_then(|file| Self::read(&mut BufReader::new(file), crc))
+            .map_err(|e| e.at(path))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        self.write(&mut bytes)
+            .expect("writing to a vector should not fail");
+        bytes
+    }
+
+    pub fn save(&self, path: &Path, options: &SaveOptions) -> Result<()> {
+        replace(path, &self.to_bytes(), options)
+    }
+
+    pub fn header(&self) -> Header {
+        Header::parse(&self.chunks[0])
+    }
+
+    pub fn find(&self, kind: &[u8; 4]) -> Option<&Chunk> {
+        self.chunks.iter().find(|c| c.is(kind))
+    }
+
+    pub fn position(&self, kind: &[u8; 4]) -> Option<usize> {
+        self.chunks.iter().position(|c| c.is(kind))
+    }
+}
+
+fn read_exact(reader: &mut impl Read, buffer: &mut [u8]) -> Result<()> {
+    match reader.read_exact(buffer) {
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Err(Error::Truncated.into()),
+        result => Ok(result?),
+    }
+}
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum Error {
+    #[error("not a png")]
+    NotPng,
+
+    #[error("corrupt header: IHDR is not the first chunk")]
+    MissingHeader,
+
+    #[error("corrupt header: IHDR has a length of {0} instead of 13")]
+    HeaderLength(usize),
+
+    #[error("corrupt header: IHDR has a mismatched crc")]
+    HeaderCrc,
+
+    #[error("truncated chunk")]
+    Truncated,
+}
@@ -1,3 +1,14 @@
+pub use crate::batch::*;
+pub use crate::config::*;
 pub use crate::crc::*;
 pub use crate::crop::*;
+pub use crate::error::Error;
+pub use crate::glob::*;
 pub use crate::grab::*;
+pub use crate::ignore::*;
+pub use crate::journal::*;
+pub use crate::png::*;
+pub use crate::record::*;
+pub use crate::save::*;
+pub use crate::verify::*;
+pub use crate::walk::*;
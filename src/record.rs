@@ -0,0 +1,402 @@
+use crate::crc::*;
+use crate::error::*;
+use crate::grab::*;
+use crate::json::{self, Value};
+use crate::png::PngFile;
+use crate::save::*;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+
+///The size and grab offset of a single png as written by the structured output formats
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    pub offset: Option<(i32, i32)>,
+}
+
+impl Record {
+    ///Reads the size from the header and the offset from the first grab chunk
+    pub fn read(path: &Path, crc: &Crc32) -> Result<Self> {
+        Self::read_with_grabs(path, crc).map(|(record, _)| record)
+    }
+
+    ///Same as `read` but also returns every grab chunk in the order they appear, so that duplicates can be told apart without reading the file again
+    pub fn read_with_grabs(path: &Path, crc: &Crc32) -> Result<(Self, Vec<(i32, i32)>)> {
+        let png = PngFile::open(path, crc)?;
+        let header = png.header();
+        let grabs = all_grabs(&png).map_err(|e| e.at(path))?;
+        let record = Self {
+            path: path.to_path_buf(),
+            width: header.width,
+            height: header.height,
+            offset: grabs.first().copied(),
+        };
+        Ok((record, grabs))
+    }
+}
+
+///A machine-readable format for records
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    ///An array of objects
+    Json,
+    ///Comma-separated values with a header row
+    Csv,
+    ///Tab-separated values with a header row
+    Tsv,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(source: &str) -> std::result::Result<Self, Self::Err> {
+        match source {
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            _ => Err(format!(
+                "expected 'json', 'csv', or 'tsv', found '{source}'"
+            )),
+        }
+    }
+}
+
+impl Format {
+    ///Picks the format from the extension of the path (anything other than json or tsv is read as csv)
+    pub fn from_extension(path: &Path) -> Self {
+        let extension = path.extension().unwrap_or_default().to_ascii_lowercase();
+        match extension.to_str() {
+            Some("json") => Format::Json,
+            Some("tsv") => Format::Tsv,
+            _ => Format::Csv,
+        }
+    }
+}
+
+const columns: [&str; 6] = ["path", "x", "y", "width", "height", "has_grab"];
+
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn csv_field(text: &str) -> String {
+    match text.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", text.replace('"', "\"\"")),
+        false => text.into(),
+    }
+}
+
+fn tsv_field(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn fields(record: &Record) -> [String; 6] {
+    let (x, y) = match record.offset {
+        Some((x, y)) => (x.to_string(), y.to_string()),
+        None => Default::default(),
+    };
+    [
+        record.path.to_string_lossy().into_owned(),
+        x,
+        y,
+        record.width.to_string(),
+        record.height.to_string(),
+        record.offset.is_some().to_string(),
+    ]
+}
+
+///Writes every record in the given format
+pub fn write_records(
+    writer: &mut impl Write,
+    records: &[Record],
+    format: Format,
+) -> io::Result<()> {
+    match format {
+        Format::Json => {
+            writeln!(writer, "[")?;
+            for (i, record) in records.iter().enumerate() {
+                let offset = |value: Option<i32>| value.map_or("null".into(), |v| v.to_string());
+                writeln!(
+                    writer,
+                    "  {{\"path\": {}, \"x\": {}, \"y\": {}, \"width\": {}, \"height\": {}, \"has_grab\": {}}}{}",
+                    json_string(&record.path.to_string_lossy()),
+                    offset(record.offset.map(|(x, _)| x)),
+                    offset(record.offset.map(|(_, y)| y)),
+                    record.width,
+                    record.height,
+                    record.offset.is_some(),
+                    if i + 1 < records.len() { "," } else { "" }
+                )?;
+            }
+            writeln!(writer, "]")
+        }
+        Format::Csv | Format::Tsv => {
+            let (separator, field): (&str, fn(&str) -> String) = match format {
+                Format::Csv => (",", csv_field),
+                _ => ("\t", tsv_field),
+            };
+            writeln!(writer, "{}", columns.join(separator))?;
+            for record in records {
+                let row: Vec<String> = fields(record).iter().map(|f| field(f)).collect();
+                writeln!(writer, "{}", row.join(separator))?;
+            }
+            Ok(())
+        }
+    }
+}
+
+///A row of an offsets manifest, where the offset is a pair of calc expressions (or None to remove the grab chunk, which takes an x and y that are there but empty)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Row {
+    pub path: PathBuf,
+    pub offset: Option<(String, String)>,
+    ///The line of the manifest the row is on
+    pub line: usize,
+}
+
+fn given(value: Option<&str>) -> Option<&str> {
+    value.map(str::trim).filter(|v| !v.is_empty())
+}
+
+fn row(path: &str, x: Option<&str>, y: Option<&str>, line: usize) -> Result<Row> {
+    let offset = match (given(x), given(y)) {
+        (Some(x), Some(y)) => Some((x.to_string(), y.to_string())),
+        (None, None) => None,
+        _ => return parse_error(line, "x and y must both be given or both be empty"),
+    };
+    match path.trim() {
+        "" => parse_error(line, "missing path"),
+        path => Ok(Row {
+            path: path.into(),
+            offset,
+            line,
+        }),
+    }
+}
+
+///Fails if a file (as told apart by `key`) is listed again with a different offset, since only one of its rows can be applied
+pub(crate) fn check_duplicates(rows: &[Row], key: impl Fn(&Path) -> PathBuf) -> Result<()> {
+    let mut first: HashMap<PathBuf, &Row> = HashMap::new();
+    for row in rows {
+        match first.entry(key(&row.path)) {
+            Entry::Occupied(entry) if entry.get().offset != row.offset => {
+                let reason = format!(
+                    "{:?} is also on line {} with a different offset",
+                    row.path,
+                    entry.get().line
+                );
+                return parse_error(row.line, reason);
+            }
+            Entry::Occupied(_) => (),
+            Entry::Vacant(entry) => {
+                entry.insert(row);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn split_csv(line: &str) -> Option<Vec<String>> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        let field = fields.last_mut()?;
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if field.is_empty() => quoted = true,
+            (',', false) => fields.push(String::new()),
+            (c, _) => field.push(c),
+        }
+    }
+    (!quoted).then_some(fields)
+}
+
+fn split_tsv(line: &str) -> Vec<String> {
+    line.split('\t')
+        .map(|field| {
+            let mut unescaped = String::new();
+            let mut chars = field.chars();
+            while let Some(c) = chars.next() {
+                match (c, chars.clone().next()) {
+                    ('\\', Some(next @ ('t' | 'n' | 'r' | '\\'))) => {
+                        chars.next();
+                        unescaped.push(match next {
+                            't' => '\t',
+                            'n' => '\n',
+                            'r' => '\r',
+                            _ => '\\',
+                        });
+                    }
+                    (c, _) => unescaped.push(c),
+                }
+            }
+            unescaped
+        })
+        .collect()
+}
+
+fn parse_table(text: &str, format: Format) -> Result<Vec<Row>> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line))
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| match format {
+            Format::Tsv => Ok((number, split_tsv(line))),
+            _ => match split_csv(line) {
+                Some(fields) => Ok((number, fields)),
+                None => parse_error(number, "unterminated quote"),
+            },
+        })
+        .peekable();
+
+    //The columns are found by name when the first row is a header, otherwise they are path, x, and y in that order
+    let mut indices = [0, 1, 2];
+    let mut column_count = indices.len();
+    if let Some(Ok((number, header))) = lines.peek() {
+        let names: Vec<String> = header.iter().map(|f| f.trim().to_lowercase()).collect();
+        if names.iter().any(|name| name == "path") {
+            let number = *number;
+            for (index, column) in indices.iter_mut().zip(["path", "x", "y"]) {
+                match names.iter().position(|name| name == column) {
+                    Some(position) => *index = position,
+                    None => return parse_error(number, format!("missing the {column} column")),
+                }
+            }
+            column_count = header.len();
+            lines.next();
+        }
+    }
+    lines
+        .map(|line| {
+            let (number, fields) = line?;
+            //A row is only left without an offset if its x and y are there but empty, so a stray line cannot remove a grab chunk
+            if fields.len() != column_count {
+                let reason = format!("expected {column_count} columns, found {}", fields.len());
+                return parse_error(number, reason);
+            }
+            let field = |index: usize| fields.get(index).map(String::as_str);
+            row(
+                field(indices[0]).unwrap_or_default(),
+                field(indices[1]),
+                field(indices[2]),
+                number,
+            )
+        })
+        .collect()
+}
+
+fn parse_json(text: &str) -> Result<Vec<Row>> {
+    let rows = match json::parse(text) {
+        Ok(Value::Array(rows)) => rows,
+        Ok(_) => return parse_error(1, "expected an array of objects"),
+        Err((reason, line)) => return parse_error(line, reason),
+    };
+    rows.iter()
+        .map(|(object, line)| {
+            let text = |key| match object.get(key) {
+                None => parse_error(*line, format!("missing {key}")),
+                Some(Value::Null) => Ok(None),
+                Some(Value::Number(text) | Value::String(text)) => Ok(Some(text.as_str())),
+                Some(_) => parse_error(*line, format!("{key} must be a number or a string")),
+            };
+            match object {
+                Value::Object(_) => row(
+                    text("path")?.unwrap_or_default(),
+                    text("x")?,
+                    text("y")?,
+                    *line,
+                ),
+                _ => parse_error(*line, "expected an object"),
+            }
+        })
+        .collect()
+}
+
+///Parses the rows of an offsets manifest (csv and tsv columns are found by name if the first row is a header)
+pub fn parse_rows(text: &str, format: Format) -> Result<Vec<Row>> {
+    let rows = match format {
+        Format::Json => parse_json(text)?,
+        Format::Csv | Format::Tsv => parse_table(text, format)?,
+    };
+    check_duplicates(&rows, |path| {
+        path.components()
+            .filter(|c| *c != Component::CurDir)
+            .collect()
+    })?;
+    Ok(rows)
+}
+
+///Reads the rows of an offsets manifest in the format of its extension, with relative paths being relative to the manifest
+pub fn read_rows(path: &Path) -> Result<Vec<Row>> {
+    let text = std::fs::read_to_string(path).map_err(|e| Error::from(e).at(path))?;
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let rows = parse_rows(&text, Format::from_extension(path)).map_err(|e| e.at(path))?;
+    Ok(rows
+        .into_iter()
+        .map(|row| Row {
+            path: directory.join(row.path),
+            ..row
+        })
+        .collect())
+}
+
+//Both paths are made absolute first, but `..` is left as it is
+pub(crate) fn relative_to(path: &Path, base: &Path) -> io::Result<PathBuf> {
+    let (path, base) = (std::path::absolute(path)?, std::path::absolute(base)?);
+    let common = path
+        .components()
+        .zip(base.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut relative: PathBuf = base
+        .components()
+        .skip(common)
+        .map(|_| Component::ParentDir)
+        .collect();
+    relative.extend(path.components().skip(common));
+    Ok(relative)
+}
+
+///Writes the records sorted by path to the manifest at `output`, with the paths made relative to it so that `read_rows` can find them again
+pub fn export(
+    output: &Path,
+    mut records: Vec<Record>,
+    format: Format,
+    options: &SaveOptions,
+) -> Result<()> {
+    let directory = output.parent().unwrap_or(Path::new(""));
+    for record in &mut records {
+        record.path =
+            relative_to(&record.path, directory).map_err(|e| Error::from(e).at(output))?;
+    }
+    records.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut bytes = vec![];
+    write_records(&mut bytes, &records, format)?;
+    replace(output, &bytes, options)
+}
@@ -0,0 +1,121 @@
+use crate::error::*;
+use crate::journal::{Backup, Journal};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+///Settings for how the modified pngs are written back to disk
+#[derive(Clone, Debug, Default)]
+pub struct SaveOptions {
+    ///Keep the modification time of the original file
+    pub keep_modified: bool,
+    ///Go through every change without writing anything
+    pub dry_run: bool,
+    ///Back up every file before it is changed so the run can be undone
+    pub journal: Option<Arc<Journal>>,
+}
+
+//Every write gets its own name so that threads writing the same file never share a temporary file
+fn temporary_path(path: &Path) -> PathBuf {
+    static count: AtomicUsize = AtomicUsize::new(0);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let id = count.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(".{name}.{}.{id}.putpng", std::process::id()))
+}
+
+fn write_temporary(
+    mut file: File,
+    original: &Path,
+    bytes: &[u8],
+    options: &SaveOptions,
+) -> Result<()> {
+    let metadata = fs::metadata(original).ok();
+    file.write_all(bytes)?;
+    if let Some(metadata) = metadata {
+        file.set_permissions(metadata.permissions())?;
+        if options.keep_modified {
+            file.set_modified(metadata.modified()?)?;
+        }
+    }
+    file.sync_all()?;
+    Ok(())
+}
+
+//The file a symlink points to, so that the link itself is kept and the file behind it is replaced
+fn target(path: &Path) -> Result<PathBuf> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            fs::canonicalize(path).map_err(|e| Error::from(e).at(path))
+        }
+        _ => Ok(path.to_path_buf()),
+    }
+}
+
+///Replaces (or creates) the specified file by writing a temporary file next to it and renaming it over the original, or over the file it links to if it is a symlink (does nothing for a dry run)
+pub fn replace(path: &Path, bytes: &[u8], options: &SaveOptions) -> Result<()> {
+    if options.dry_run {
+        return Ok(());
+    }
+    let target = target(path)?;
+    let temporary = temporary_path(&target);
+    //Only a temporary file that was created here is removed on failure
+    let file = File::options()
+        .write(true)
+        .create_new(true)
+        .open(&temporary)
+        .map_err(|e| Error::from(e).at(path))?;
+    if let Err(e) = write_temporary(file, &target, bytes, options) {
+        let _ = fs::remove_file(&temporary);
+        return Err(e.at(path));
+    }
+    if let Err(e) = fs::rename(&temporary, &target) {
+        let _ = fs::remove_file(&temporary);
+        return Err(Error::from(e).at(path));
+    }
+    #[cfg(unix)]
+    if let Some(parent) = target.parent() {
+        let parent = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+        File::open(parent)
+            .and_then(|directory| directory.sync_all())
+            .map_err(|e| Error::from(e).at(path))?;
+    }
+    Ok(())
+}
+
+///Runs `edit` on the contents of the specified file and only writes them back if they were changed
+pub(crate) fn edit<T>(
+    path: &Path,
+    options: &SaveOptions,
+    edit: impl FnOnce(&mut Vec<u8>) -> Result<T>,
+) -> Result<T> {
+    edit_with(path, options, edit, |_| None)
+}
+
+///Same as `edit` but `grab_only` can give the previous offset to journal instead of the whole file when only the offset was changed
+pub(crate) fn edit_with<T>(
+    path: &Path,
+    options: &SaveOptions,
+    edit: impl FnOnce(&mut Vec<u8>) -> Result<T>,
+    grab_only: impl FnOnce(&T) -> Option<(i32, i32)>,
+) -> Result<T> {
+    let original = fs::read(path).map_err(|e| Error::from(e).at(path))?;
+    let mut bytes = original.clone();
+    let result = edit(&mut bytes).map_err(|e| e.at(path))?;
+    if bytes != original {
+        if let Some(journal) = options.journal.as_ref().filter(|_| !options.dry_run) {
+            let backup = match grab_only(&result) {
+                Some(offset) => Backup::Grab(offset),
+                None => Backup::File(&original),
+            };
+            journal.record(path, backup).map_err(|e| e.at(path))?;
+        }
+        replace(path, &bytes, options)?;
+    }
+    Ok(result)
+}
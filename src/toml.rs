@@ -0,0 +1,278 @@
+//Just enough of a toml reader for the config file: tables, arrays of tables, and keys holding strings, integers (kept as their text), booleans, or arrays of those
+
+use crate::cursor::{Cursor, Error};
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Value {
+    String(String),
+    Integer(String),
+    Bool(bool),
+    Array(Vec<Value>),
+}
+
+///A `[name]` or `[[name]]` table (the keys before the first table go in one with an empty name)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Table {
+    pub(crate) name: String,
+    ///Whether it was written as `[[name]]`
+    pub(crate) array: bool,
+    ///The line of the header
+    pub(crate) line: usize,
+    ///Each value is paired with the line its key is on
+    pub(crate) fields: Vec<(String, Value, usize)>,
+}
+
+struct Parser<'a> {
+    cursor: Cursor<'a>,
+}
+
+impl Parser<'_> {
+    fn skip_comment(&mut self) {
+        if self.cursor.get() == Some(b'#') {
+            while self.cursor.get().is_some_and(|b| b != b'\n') {
+                self.cursor.index += 1;
+            }
+        }
+    }
+
+    //Skips spaces and tabs, and newlines and comments too if `lines` is set
+    fn skip_whitespace(&mut self, lines: bool) {
+        loop {
+            match self.cursor.get() {
+                Some(b' ' | b'\t') => self.cursor.index += 1,
+                Some(b'\r' | b'\n') if lines => self.cursor.index += 1,
+                Some(b'#') if lines => self.skip_comment(),
+                _ => return,
+            }
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), Error> {
+        match self.cursor.get() {
+            Some(b) if b == byte => {
+                self.cursor.index += 1;
+                Ok(())
+            }
+            _ => self.cursor.error(&format!("expected '{}'", byte as char)),
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<(), Error> {
+        self.skip_whitespace(false);
+        self.skip_comment();
+        match self.cursor.get() {
+            None | Some(b'\n') => Ok(()),
+            Some(b'\r') if self.cursor.text.get(self.cursor.index + 1) == Some(&b'\n') => Ok(()),
+            Some(_) => self.cursor.error("expected the end of the line"),
+        }
+    }
+
+    fn key(&mut self) -> Result<String, Error> {
+        self.skip_whitespace(false);
+        let key = match self.cursor.get() {
+            Some(b'"') => self.basic_string()?,
+            Some(b'\'') => self.literal_string()?,
+            _ => {
+                let start = self.cursor.index;
+                while self
+                    .cursor
+                    .get()
+                    .is_some_and(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+                {
+                    self.cursor.index += 1;
+                }
+                match start == self.cursor.index {
+                    true => return self.cursor.error("expected a key"),
+                    false => {
+                        String::from_utf8_lossy(&self.cursor.text[start..self.cursor.index]).into()
+                    }
+                }
+            }
+        };
+        self.skip_whitespace(false);
+        match self.cursor.get() {
+            Some(b'.') => self.cursor.error("dotted keys are not supported"),
+            _ => Ok(key),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, Error> {
+        self.skip_whitespace(false);
+        match self.cursor.get() {
+            Some(b'"') => self.basic_string().map(Value::String),
+            Some(b'\'') => self.literal_string().map(Value::String),
+            Some(b'[') => self.array(),
+            Some(b't' | b'f') => self.boolean(),
+            Some(b'+' | b'-' | b'0'..=b'9') => self.integer(),
+            Some(b'{') => self.cursor.error("inline tables are not supported"),
+            Some(_) => self.cursor.error("unexpected character"),
+            None => self.cursor.error("unexpected end"),
+        }
+    }
+
+    fn boolean(&mut self) -> Result<Value, Error> {
+        for (word, value) in [("true", true), ("false", false)] {
+            if self.cursor.text[self.cursor.index..].starts_with(word.as_bytes()) {
+                self.cursor.index += word.len();
+                return Ok(Value::Bool(value));
+            }
+        }
+        self.cursor.error("unexpected character")
+    }
+
+    fn integer(&mut self) -> Result<Value, Error> {
+        let start = self.cursor.index;
+        while self
+            .cursor
+            .get()
+            .is_some_and(|b| b.is_ascii_alphanumeric() || b"+-_.".contains(&b))
+        {
+            self.cursor.index += 1;
+        }
+        let text =
+            String::from_utf8_lossy(&self.cursor.text[start..self.cursor.index]).replace('_', "");
+        let digits = text.strip_prefix(['+', '-']).unwrap_or(&text);
+        match !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+            true => Ok(Value::Integer(text)),
+            false => self.cursor.error("only whole numbers are supported"),
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String, Error> {
+        self.expect(b'\'')?;
+        let start = self.cursor.index;
+        loop {
+            match self.cursor.get() {
+                None | Some(b'\n') => return self.cursor.error("unterminated string"),
+                Some(b'\'') => break,
+                Some(_) => self.cursor.index += 1,
+            }
+        }
+        let text = &self.cursor.text[start..self.cursor.index];
+        self.cursor.index += 1;
+        String::from_utf8(text.to_vec()).or_else(|_| self.cursor.error("invalid utf-8"))
+    }
+
+    fn basic_string(&mut self) -> Result<String, Error> {
+        self.expect(b'"')?;
+        let mut bytes = vec![];
+        loop {
+            match self.cursor.get() {
+                None | Some(b'\n') => return self.cursor.error("unterminated string"),
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    self.cursor.index += 1;
+                    let escaped = match self.cursor.get() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            let code = self
+                                .cursor
+                                .hex(self.cursor.index + 1)
+                                .and_then(char::from_u32);
+                            match code {
+                                Some(c) => {
+                                    self.cursor.index += 4;
+                                    c
+                                }
+                                None => return self.cursor.error("invalid unicode escape"),
+                            }
+                        }
+                        _ => return self.cursor.error("invalid escape"),
+                    };
+                    bytes.extend_from_slice(escaped.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                Some(b) => bytes.push(b),
+            }
+            self.cursor.index += 1;
+        }
+        self.cursor.index += 1;
+        String::from_utf8(bytes).or_else(|_| self.cursor.error("invalid utf-8"))
+    }
+
+    fn array(&mut self) -> Result<Value, Error> {
+        self.expect(b'[')?;
+        self.cursor.enter()?;
+        let mut values = vec![];
+        loop {
+            self.skip_whitespace(true);
+            if self.cursor.get() == Some(b']') {
+                self.cursor.index += 1;
+                self.cursor.leave();
+                return Ok(Value::Array(values));
+            }
+            values.push(self.value()?);
+            self.skip_whitespace(true);
+            match self.cursor.get() {
+                Some(b',') => self.cursor.index += 1,
+                Some(b']') => (),
+                _ => return self.cursor.error("expected ',' or ']'"),
+            }
+        }
+    }
+
+    fn header(&mut self) -> Result<Table, Error> {
+        let line = self.cursor.line();
+        self.expect(b'[')?;
+        let array = self.cursor.get() == Some(b'[');
+        if array {
+            self.cursor.index += 1;
+        }
+        let name = self.key()?;
+        self.expect(b']')?;
+        if array {
+            self.expect(b']')?;
+        }
+        Ok(Table {
+            name,
+            array,
+            line,
+            fields: vec![],
+        })
+    }
+}
+
+///Parses the text into its tables in the order they appear
+pub(crate) fn parse(text: &str) -> Result<Vec<Table>, Error> {
+    let mut parser = Parser {
+        cursor: Cursor::new(text),
+    };
+    let mut tables = vec![Table {
+        line: 1,
+        ..Default::default()
+    }];
+    loop {
+        parser.skip_whitespace(true);
+        if parser.cursor.get().is_none() {
+            return Ok(tables);
+        }
+        if parser.cursor.get() == Some(b'[') {
+            let table = parser.header()?;
+            let defined = |t: &Table| t.name == table.name && !(t.array && table.array);
+            if tables.iter().skip(1).any(defined) {
+                return parser
+                    .cursor
+                    .error(&format!("'{}' is defined more than once", table.name));
+            }
+            tables.push(table);
+        } else {
+            let line = parser.cursor.line();
+            let key = parser.key()?;
+            parser.expect(b'=')?;
+            let value = parser.value()?;
+            let table = tables.last_mut().expect("there is always a table");
+            if table.fields.iter().any(|(k, _, _)| *k == key) {
+                return parser
+                    .cursor
+                    .error(&format!("'{key}' is defined more than once"));
+            }
+            table.fields.push((key, value, line));
+        }
+        parser.end_of_line()?;
+    }
+}
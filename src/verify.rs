@@ -0,0 +1,118 @@
+use crate::crc::*;
+use crate::error::*;
+use crate::png::{self, Chunk};
+use std::path::Path;
+
+///Checks every chunk of the specified png and returns all of the problems found
+pub fn verify(path: &Path, crc: &Crc32) -> Result<Vec<Problem>> {
+    let bytes = std::fs::read(path).map_err(|e| Error::from(e).at(path))?;
+    Ok(verify_bytes(&bytes, crc))
+}
+
+///Checks every chunk of the png held in `bytes` and returns all of the problems found
+pub fn verify_bytes(bytes: &[u8], crc: &Crc32) -> Vec<Problem> {
+    let Some(mut rest) = bytes.strip_prefix(&png::signature) else {
+        return vec![Problem::NotPng];
+    };
+    let mut problems = vec![];
+    let mut grab_count = 0;
+    let mut index = 0;
+
+    loop {
+        let offset = bytes.len() - rest.len();
+        if rest.is_empty() {
+            problems.push(Problem::MissingEnd);
+            break;
+        }
+        let Ok(chunk) = Chunk::read(&mut rest) else {
+            problems.push(Problem::Truncated { offset });
+            problems.push(Problem::MissingEnd);
+            break;
+        };
+        let kind = String::from_utf8_lossy(&chunk.kind).into_owned();
+
+        let calculated = Chunk::new(crc, &chunk.kind, chunk.data.clone()).crc;
+        if calculated != chunk.crc {
+            problems.push(Problem::CrcMismatch {
+                kind: kind.clone(),
+                offset,
+                stored: chunk.crc,
+                calculated,
+            });
+        }
+        if let Some(expected) = expected_length(&chunk.kind)
+            && chunk.data.len() != expected
+        {
+            problems.push(Problem::BadLength {
+                kind: kind.clone(),
+                offset,
+                length: chunk.data.len(),
+                expected,
+            });
+        }
+        if index == 0 && !chunk.is(b"IHDR") {
+            problems.push(Problem::MissingHeader);
+        }
+        if chunk.is(b"grAb") {
+            grab_count += 1;
+        }
+        if chunk.is(b"IEND") {
+            if !rest.is_empty() {
+                problems.push(Problem::TrailingData(rest.len()));
+            }
+            break;
+        }
+        index += 1;
+    }
+
+    if grab_count > 1 {
+        problems.push(Problem::DuplicateGrab(grab_count));
+    }
+    problems
+}
+
+fn expected_length(kind: &[u8; 4]) -> Option<usize> {
+    match kind {
+        b"IHDR" => Some(13),
+        b"IEND" => Some(0),
+        b"grAb" => Some(8),
+        _ => None,
+    }
+}
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum Problem {
+    #[error("not a png")]
+    NotPng,
+
+    #[error("IHDR is not the first chunk")]
+    MissingHeader,
+
+    #[error("{kind} chunk at byte {offset} has a crc of {stored:08x} instead of {calculated:08x}")]
+    CrcMismatch {
+        kind: String,
+        offset: usize,
+        stored: u32,
+        calculated: u32,
+    },
+
+    #[error("{kind} chunk at byte {offset} has a length of {length} instead of {expected}")]
+    BadLength {
+        kind: String,
+        offset: usize,
+        length: usize,
+        expected: usize,
+    },
+
+    #[error("chunk at byte {offset} is truncated")]
+    Truncated { offset: usize },
+
+    #[error("missing IEND chunk")]
+    MissingEnd,
+
+    #[error("{0} bytes of data after IEND")]
+    TrailingData(usize),
+
+    #[error("{0} grAb chunks instead of one")]
+    DuplicateGrab(usize),
+}
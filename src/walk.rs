@@ -0,0 +1,137 @@
+use crate::error::*;
+use crate::glob::Pattern;
+use crate::ignore::Ignores;
+use crate::journal::journal_directory;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+///Which files to pick out of the given paths
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    ///Go through directories and everything below them for png files
+    pub recursive: bool,
+    ///If not empty, only paths matching one of these are kept
+    pub include: Vec<Pattern>,
+    pub exclude: Vec<Pattern>,
+    ///Leave out the paths ignored by a `.putpngignore` in any of their parent directories
+    pub ignore_files: bool,
+}
+
+//Makes the path absolute and removes any `.` and `..` without touching the file system
+pub(crate) fn normalize(path: &Path) -> Result<PathBuf> {
+    let absolute = std::path::absolute(path).map_err(|e| Error::from(e).at(path))?;
+    let mut normalized = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    Ok(normalized)
+}
+
+//The file the path points to, so that two paths to the same file (through a symlink or with `..`) compare equal
+pub(crate) fn identity(path: &Path) -> PathBuf {
+    fs::canonicalize(path)
+        .or_else(|_| normalize(path))
+        .unwrap_or(path.to_path_buf())
+}
+
+fn is_png(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+}
+
+impl Filter {
+    pub fn is_included(&self, path: &Path) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches(path)))
+            && !self.exclude.iter().any(|p| p.matches(path))
+    }
+
+    fn is_ignored(&self, ignores: &mut Ignores, path: &Path, is_dir: bool) -> Result<bool> {
+        match self.ignore_files {
+            true => ignores.is_ignored(path, is_dir),
+            false => Ok(false),
+        }
+    }
+
+    fn walk(
+        &self,
+        directory: &Path,
+        ignores: &mut Ignores,
+        files: &mut Vec<PathBuf>,
+    ) -> Result<()> {
+        let mut entries = fs::read_dir(directory)
+            .and_then(|entries| entries.collect::<std::io::Result<Vec<_>>>())
+            .map_err(|e| Error::from(e).at(directory))?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let path = entry.path();
+            let file_type = entry.file_type().map_err(|e| Error::from(e).at(&path))?;
+            //Symbolic links to directories are not followed so that a link to a parent cannot loop forever, and excluded directories are skipped as a whole
+            if file_type.is_dir() {
+                let excluded = self.exclude.iter().any(|p| p.matches(&path));
+                if entry.file_name() != journal_directory
+                    && !excluded
+                    && !self.is_ignored(ignores, &path, true)?
+                {
+                    self.walk(&path, ignores, files)?;
+                }
+            } else if is_png(&path)
+                && self.is_included(&path)
+                && !self.is_ignored(ignores, &path, false)?
+            {
+                files.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    ///Expands directories into the png files below them (if recursive) in sorted order and leaves out every file that is not a png (by its extension, even if it was given explicitly) and every path that is not included or is ignored
+    pub fn files<'a>(&self, paths: impl IntoIterator<Item = &'a Path>) -> Result<Vec<PathBuf>> {
+        let mut ignores = Ignores::default();
+        let mut files = vec![];
+        for path in paths {
+            let is_dir = path.is_dir();
+            if self.is_ignored(&mut ignores, path, is_dir)? {
+                continue;
+            }
+            if self.recursive && is_dir {
+                self.walk(path, &mut ignores, &mut files)?;
+            } else if (is_dir || is_png(path)) && self.is_included(path) {
+                files.push(path.to_path_buf());
+            }
+        }
+        Ok(files)
+    }
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::OsStr::from_bytes(bytes).into()
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    String::from_utf8_lossy(bytes).into_owned().into()
+}
+
+///Splits a list of paths separated by NUL characters, or by lines if there are none (empty entries are skipped)
+pub fn parse_list(bytes: &[u8]) -> Vec<PathBuf> {
+    let entries: Vec<&[u8]> = match bytes.contains(&0) {
+        true => bytes.split(|b| *b == 0).collect(),
+        false => bytes
+            .split(|b| *b == b'\n')
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+            .collect(),
+    };
+    entries
+        .into_iter()
+        .filter(|entry| !entry.is_empty())
+        .map(path_from_bytes)
+        .collect()
+}